@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use actix_web::{dev::Server, web, App, HttpResponse, HttpServer};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{check_color_temp, config_file, parse_color, AppliedEffect, ApiConfig, AppError, AppState, Backend, Config, EventConfig, EventRecord, EventSource, LightEffect, ZeroBrightness, MAX_BRIGHTNESS, VALID_ALERTS};
+
+const PREVIEW_HTML: &str = include_str!("../static/preview.html");
+const DASHBOARD_HTML: &str = include_str!("../static/dashboard.html");
+// Handled events listed on the dashboard
+const DASHBOARD_EVENTS: usize = 25;
+
+// Partial update for a single effect; omitted fields are left unchanged
+#[derive(Debug, Deserialize)]
+struct EffectPatch {
+    color: Option<String>,
+    brightness: Option<u8>,
+    alert: Option<String>,
+    #[serde(default, deserialize_with = "crate::duration::deserialize_option")]
+    duration: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct LightInfo {
+    id: usize,
+    name: String,
+    on: bool,
+    brightness: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+struct Status {
+    events: BTreeMap<&'static str, bool>,
+}
+
+// What the dashboard polls for; lights come from /lights
+#[derive(Debug, Serialize)]
+struct Dashboard<'a> {
+    streamlabs_connected: bool,
+    // False once a light command fails, until one succeeds again
+    lights_responding: bool,
+    backend: Backend,
+    dry_run: bool,
+    active_show: Option<&'a str>,
+    last_effect: Option<AppliedEffect>,
+    // Newest first
+    events: Vec<EventRecord>,
+}
+
+#[derive(Debug, Serialize)]
+struct Shows<'a> {
+    active: Option<&'a str>,
+    shows: Vec<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EffectQuery {
+    tier: Option<usize>,
+    #[serde(default)]
+    persist: bool,
+}
+
+pub async fn serve(state: Arc<AppState>, api: ApiConfig) {
+    info!("Starting HTTP API on {}:{}", api.bind, api.port);
+    match build_server(state, &api) {
+        Ok(server) => {
+            if let Err(e) = server.await {
+                error!("HTTP API stopped: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to bind HTTP API on {}:{}: {}", api.bind, api.port, e),
+    }
+}
+
+fn build_server(state: Arc<AppState>, api: &ApiConfig) -> std::io::Result<Server> {
+    let data = web::Data::from(state);
+    let preview_ui = api.preview_ui;
+    let dashboard = api.dashboard;
+    let server = HttpServer::new(move || {
+        let app = App::new()
+            .app_data(data.clone())
+            .route("/status", web::get().to(get_status))
+            .route("/events/{type}/enable", web::post().to(enable_event))
+            .route("/events/{type}/disable", web::post().to(disable_event))
+            .route("/lights", web::get().to(get_lights))
+            .route("/light_groups", web::get().to(get_light_groups))
+            .route("/effect", web::post().to(post_effect))
+            .route("/config/events", web::get().to(get_events))
+            .route("/history.csv", web::get().to(get_history_csv))
+            .route("/shows", web::get().to(get_shows))
+            .route("/shows/deactivate", web::post().to(deactivate_show))
+            .route("/shows/{name}/activate", web::post().to(activate_show))
+            .route("/config/events/{type}/effect", web::patch().to(patch_effect));
+        let app = if dashboard {
+            app.route("/dashboard", web::get().to(dashboard_page))
+                .route("/dashboard/state", web::get().to(get_dashboard))
+        } else {
+            app
+        };
+        if preview_ui {
+            app.route("/", web::get().to(preview_page))
+        } else {
+            app
+        }
+    })
+    .workers(1)
+    .bind((api.bind.as_str(), api.port))?
+    .run();
+    Ok(server)
+}
+
+async fn preview_page() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(PREVIEW_HTML)
+}
+
+async fn dashboard_page() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(DASHBOARD_HTML)
+}
+
+async fn get_dashboard(state: web::Data<AppState>) -> HttpResponse {
+    let config = state.config();
+    HttpResponse::Ok().json(Dashboard {
+        streamlabs_connected: state.streamlabs_connected.load(Ordering::SeqCst),
+        lights_responding: !state.bridge_lost.load(Ordering::SeqCst),
+        backend: config.backend,
+        dry_run: config.dry_run,
+        active_show: config.active_show.as_deref(),
+        last_effect: state.last_effect.lock().clone(),
+        events: state.latest_event_records(DASHBOARD_EVENTS),
+    })
+}
+
+async fn get_status(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(Status {
+        events: state.config().active_events().enabled_states(),
+    })
+}
+
+async fn enable_event(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    set_event_enabled(&state, &path.into_inner(), true)
+}
+
+async fn disable_event(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    set_event_enabled(&state, &path.into_inner(), false)
+}
+
+/// Flips an event type's flag in the base mappings, every time profile and every show.
+fn set_event_enabled(state: &AppState, event_type: &str, enabled: bool) -> HttpResponse {
+    let mut config = (*state.config()).clone();
+    let profiles = config.time_profiles.iter_mut().map(|profile| &mut profile.events);
+    let profiles = profiles.chain(config.shows.values_mut());
+    for events in std::iter::once(&mut config.events).chain(profiles) {
+        match events.enabled_mut(event_type) {
+            Some(flag) => *flag = enabled,
+            None => return HttpResponse::NotFound().body(format!("Unknown event type: {}", event_type)),
+        }
+    }
+
+    *state.config.write() = Arc::new(config);
+    info!("{} {} events", if enabled { "Enabled" } else { "Disabled" }, event_type);
+    HttpResponse::Ok().json(Status {
+        events: state.config().active_events().enabled_states(),
+    })
+}
+
+async fn get_lights(state: web::Data<AppState>) -> HttpResponse {
+    let lights = web::block(move || state.get_lights(&**state.controller.lock())).await;
+    match lights {
+        Ok(Ok(lights)) => HttpResponse::Ok().json(lights.into_iter().map(|light| LightInfo {
+            id: light.id,
+            name: light.light.name,
+            on: light.light.state.on,
+            brightness: light.light.state.bri,
+        }).collect::<Vec<_>>()),
+        Ok(Err(e)) => HttpResponse::BadGateway().body(format!("Failed to get lights: {}", e)),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+async fn get_light_groups(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(&state.config().light_groups)
+}
+
+async fn get_events(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.config().active_events())
+}
+
+async fn get_history_csv(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(("Content-Disposition", "attachment; filename=\"lumialive-history.csv\""))
+        .body(state.event_history_csv())
+}
+
+async fn get_shows(state: web::Data<AppState>) -> HttpResponse {
+    let config = state.config();
+    let mut shows: Vec<&str> = config.shows.keys().map(String::as_str).collect();
+    shows.sort_unstable();
+    HttpResponse::Ok().json(Shows {
+        active: config.active_show.as_deref(),
+        shows,
+    })
+}
+
+async fn activate_show(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    switch_show(&state, Some(&path.into_inner()))
+}
+
+async fn deactivate_show(state: web::Data<AppState>) -> HttpResponse {
+    switch_show(&state, None)
+}
+
+/// Swaps in the config with `show` active and queues `show_transition_effect`.
+fn switch_show(state: &AppState, show: Option<&str>) -> HttpResponse {
+    let mut config = (*state.config()).clone();
+    if let Err(e) = config.select_show(show) {
+        return HttpResponse::NotFound().body(e.to_string());
+    }
+    let transition = config.show_transition_effect.clone();
+    *state.config.write() = Arc::new(config);
+    info!("Active show is now {}", show.unwrap_or("(base events)"));
+
+    if let Some(effect) = transition {
+        if let Err(e) = state.enqueue_effect(&effect) {
+            error!("Failed to queue show transition effect: {}", e);
+        }
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// Queues an arbitrary effect and returns immediately.
+async fn post_effect(state: web::Data<AppState>, effect: web::Json<LightEffect>) -> HttpResponse {
+    let effect = effect.into_inner();
+    let color = match effect.color_temp_mirek {
+        Some(mirek) => check_color_temp(mirek).map_err(|e| e.to_string()),
+        None => validate_color(&effect.color),
+    };
+    if let Err(e) = color.and_then(|_| validate_alert(&effect.alert)) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if let Err(e) = validate_brightness(&state, effect.brightness) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    info!("Previewing effect from HTTP API: {:?}", effect);
+    match state.enqueue_effect(&effect) {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(e) => HttpResponse::ServiceUnavailable().body(e.to_string()),
+    }
+}
+
+fn validate_color(color: &str) -> Result<(), String> {
+    parse_color(color).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn validate_brightness(state: &AppState, brightness: u8) -> Result<(), String> {
+    if brightness > MAX_BRIGHTNESS {
+        return Err(format!("Brightness {} is outside 0-{}", brightness, MAX_BRIGHTNESS));
+    }
+    if brightness == 0 && state.config().zero_brightness == ZeroBrightness::Reject {
+        return Err("Brightness 0 is rejected by zero_brightness".to_string());
+    }
+    Ok(())
+}
+
+fn validate_alert(alert: &str) -> Result<(), String> {
+    if !VALID_ALERTS.contains(&alert) {
+        return Err(format!("Invalid alert {:?}, expected one of {:?}", alert, VALID_ALERTS));
+    }
+    Ok(())
+}
+
+async fn patch_effect(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<EffectQuery>,
+    patch: web::Json<EffectPatch>,
+) -> HttpResponse {
+    let event_type = path.into_inner();
+
+    let valid = patch.color.as_deref().map_or(Ok(()), validate_color)
+        .and_then(|_| patch.alert.as_deref().map_or(Ok(()), validate_alert))
+        .and_then(|_| patch.brightness.map_or(Ok(()), |brightness| validate_brightness(&state, brightness)));
+    if let Err(e) = valid {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    // Edit a copy and swap it in so in-flight events keep a consistent snapshot. The change goes
+    // to the mappings events are handled with right now, which may be a time profile or show
+    let mut config = (*state.config()).clone();
+    let source = config.active_event_source();
+    let effect = match config.events_mut(&source).effect_mut(&event_type, query.tier) {
+        Ok(effect) => effect,
+        Err(e) => return HttpResponse::NotFound().body(e),
+    };
+
+    if let Some(color) = &patch.color {
+        effect.color = color.clone();
+    }
+    if let Some(brightness) = patch.brightness {
+        effect.brightness = brightness;
+    }
+    if let Some(alert) = &patch.alert {
+        effect.alert = alert.clone();
+    }
+    if let Some(duration) = patch.duration {
+        effect.duration = duration;
+    }
+    let updated = effect.clone();
+
+    if query.persist {
+        let path = config.path.clone();
+        if let Err(e) = persist_events(&path, &source, config.events_mut(&source)) {
+            error!("Failed to persist config to {}: {}", config.path, e);
+            return HttpResponse::InternalServerError().body(format!("Failed to persist config: {}", e));
+        }
+        info!("Persisted config to {}", config.path);
+    }
+
+    info!("Updated {} effect in {}: {:?}", event_type, source_name(&config, &source), updated);
+    *state.config.write() = Arc::new(config);
+    HttpResponse::Ok().json(updated)
+}
+
+/// Rewrites only the event mappings `source` names in the config file, leaving credentials
+/// (which may have been merged in from a secrets file) and other keys untouched.
+fn persist_events(path: &str, source: &EventSource, events: &EventConfig) -> Result<(), AppError> {
+    let mut file = config_file::read(path)?;
+    let section = match source {
+        EventSource::Events => &mut file["events"],
+        EventSource::TimeProfile(index) => file["time_profiles"].get_mut(*index)
+            .map(|profile| &mut profile["events"])
+            .ok_or_else(|| AppError::Config(format!("{} has no time profile {}", path, index)))?,
+        EventSource::Show(show) => &mut file["shows"][show.as_str()],
+    };
+    *section = serde_json::to_value(events)?;
+    config_file::write(path, &file)
+}
+
+fn source_name(config: &Config, source: &EventSource) -> String {
+    match source {
+        EventSource::Events => "events".to_string(),
+        EventSource::TimeProfile(index) => format!("time profile {}", config.time_profiles[*index].name),
+        EventSource::Show(show) => format!("show {}", show),
+    }
+}
@@ -0,0 +1,132 @@
+//! Reads and writes config files as JSON, TOML or YAML, chosen by the file extension
+//! (`.toml`, `.yaml`/`.yml`, anything else is JSON).
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::AppError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    fn of(path: &str) -> Format {
+        let extension = Path::new(path).extension().and_then(|extension| extension.to_str());
+        match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("toml") => Format::Toml,
+            Some("yaml" | "yml") => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// Parses the file at `path` into a JSON value, whatever format it is written in.
+pub fn read(path: &str) -> Result<Value, AppError> {
+    let content = fs::read_to_string(path)?;
+    Ok(match Format::of(path) {
+        Format::Json => serde_json::from_str(&content)?,
+        Format::Toml => toml::from_str(&content)?,
+        Format::Yaml => serde_yaml::from_str(&content)?,
+    })
+}
+
+/// Writes `value` to `path` in the format its extension names.
+pub fn write(path: &str, value: &Value) -> Result<(), AppError> {
+    let content = match Format::of(path) {
+        Format::Json => serde_json::to_string_pretty(value)?,
+        // TOML has no null; an unset optional field is simply left out
+        Format::Toml => toml::to_string_pretty(&without_nulls(value))?,
+        Format::Yaml => serde_yaml::to_string(value)?,
+    };
+    fs::write(path, content)?;
+    Ok(())
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().filter(|item| !item.is_null()).map(without_nulls).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r##"{
+        "default_state": { "on": true, "brightness": 254, "hue": 8000, "saturation": 140, "alert": "none" },
+        "events": {
+            "donation": {
+                "enabled": true,
+                "tiers": [{ "amount": 5.0, "effect": { "color": "#ff0000", "brightness": 200, "alert": "select", "duration": "2s" } }]
+            }
+        }
+    }"##;
+
+    const TOML: &str = r##"
+        [default_state]
+        on = true
+        brightness = 254
+        hue = 8000
+        saturation = 140
+        alert = "none"
+
+        [events.donation]
+        enabled = true
+
+        [[events.donation.tiers]]
+        amount = 5.0
+        effect = { color = "#ff0000", brightness = 200, alert = "select", duration = "2s" }
+    "##;
+
+    const YAML: &str = r##"
+default_state: { on: true, brightness: 254, hue: 8000, saturation: 140, alert: none }
+events:
+  donation:
+    enabled: true
+    tiers:
+      - amount: 5.0
+        effect: { color: "#ff0000", brightness: 200, alert: select, duration: 2s }
+"##;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lumialive-config-{}-{}", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn toml_and_yaml_configs_read_the_same_as_json() {
+        let mut values = Vec::new();
+        for (name, content) in [("config.json", JSON), ("config.toml", TOML), ("config.yaml", YAML)] {
+            let path = temp_path(name);
+            fs::write(&path, content).unwrap();
+            values.push(read(&path).unwrap());
+            fs::remove_file(&path).unwrap();
+        }
+        assert_eq!(values[0], values[1]);
+        assert_eq!(values[0], values[2]);
+    }
+
+    #[test]
+    fn written_files_read_back_unchanged() {
+        let value: Value = serde_json::from_str(JSON).unwrap();
+        for name in ["written.json", "written.toml", "written.yml"] {
+            let path = temp_path(name);
+            write(&path, &value).unwrap();
+            assert_eq!(read(&path).unwrap(), value, "{}", name);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! The lights effects play on, behind a trait so the effect logic isn't tied to the Hue bridge.
+
+use hueclient::{Bridge, CommandLight, HueError, IdentifiedGroup, IdentifiedLight};
+
+use crate::check_command_response;
+
+/// Boxed so the results of every command stay small; hueclient's error is well over a hundred
+/// bytes, most of it for discovery failures commands never return.
+pub type ControllerError = Box<HueError>;
+
+/// A backend that lists lights and groups and changes their state. Commands are single
+/// attempts; retries and backoff are up to the caller.
+pub trait LightController: Send {
+    fn get_lights(&self) -> Result<Vec<IdentifiedLight>, ControllerError>;
+
+    fn get_groups(&self) -> Result<Vec<IdentifiedGroup>, ControllerError>;
+
+    fn set_light(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError>;
+
+    /// `set_light` for every light in a group, in a single request.
+    fn set_group(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError>;
+
+    /// Starts the device's own version of an effect on a light, if it has one. `false` means
+    /// the caller should play the effect itself, command by command.
+    fn play_builtin(&self, _id: usize, _builtin: Builtin) -> Result<bool, ControllerError> {
+        Ok(false)
+    }
+}
+
+/// Effects some devices can run on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Strobe,
+    Rainbow,
+}
+
+impl<T: LightController + ?Sized> LightController for Box<T> {
+    fn get_lights(&self) -> Result<Vec<IdentifiedLight>, ControllerError> {
+        (**self).get_lights()
+    }
+
+    fn get_groups(&self) -> Result<Vec<IdentifiedGroup>, ControllerError> {
+        (**self).get_groups()
+    }
+
+    fn set_light(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        (**self).set_light(id, command)
+    }
+
+    fn set_group(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        (**self).set_group(id, command)
+    }
+
+    fn play_builtin(&self, id: usize, builtin: Builtin) -> Result<bool, ControllerError> {
+        (**self).play_builtin(id, builtin)
+    }
+}
+
+/// Error entries in a command response, which hueclient reports as success, only fail the
+/// command when the bridge says it is busy.
+impl LightController for Bridge {
+    fn get_lights(&self) -> Result<Vec<IdentifiedLight>, ControllerError> {
+        self.get_all_lights().map_err(Box::new)
+    }
+
+    fn get_groups(&self) -> Result<Vec<IdentifiedGroup>, ControllerError> {
+        self.get_all_groups().map_err(Box::new)
+    }
+
+    fn set_light(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        check_command_response(self.set_light_state(id, command)?, "light", id)
+    }
+
+    fn set_group(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        check_command_response(self.set_group_state(id, command)?, "group", id)
+    }
+}
+
+/// An in-memory `LightController` for tests. It records every command with the time it was
+/// sent, read from tokio's clock so tests on a paused runtime can check timing without
+/// really waiting, and applies commands to its lights so later reads see the effect.
+#[cfg(test)]
+pub mod mock {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use hueclient::{CommandLight, HueError, IdentifiedGroup, IdentifiedLight};
+    use parking_lot::Mutex;
+    use tokio::time::Instant;
+
+    use super::{ControllerError, LightController};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Target {
+        Light(usize),
+        Group(usize),
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct SentCommand {
+        pub target: Target,
+        pub command: CommandLight,
+        pub at: Instant,
+    }
+
+    /// Clones share their lights and command log, so a test can keep one to inspect.
+    #[derive(Clone, Default)]
+    pub struct MockController {
+        lights: Arc<Mutex<Vec<IdentifiedLight>>>,
+        sent: Arc<Mutex<Vec<SentCommand>>>,
+        failing: Arc<Mutex<HashSet<usize>>>,
+    }
+
+    impl MockController {
+        /// Lights with the given ids, all on at full brightness.
+        pub fn with_lights(ids: &[usize]) -> Self {
+            let lights = ids.iter().map(|&id| IdentifiedLight {
+                id,
+                light: hueclient::Light {
+                    name: format!("Light {}", id),
+                    modelid: String::new(),
+                    swversion: String::new(),
+                    uniqueid: String::new(),
+                    state: hueclient::LightState { on: true, bri: Some(254), hue: None, sat: None, ct: None, xy: None },
+                },
+            });
+            Self {
+                lights: Arc::new(Mutex::new(lights.collect())),
+                ..Self::default()
+            }
+        }
+
+        /// Makes commands to light `id` fail the way the bridge fails them for a deleted bulb.
+        /// The first failure also drops it from the light list, as if it was deleted after the
+        /// effect listed it.
+        pub fn fail_light(&self, id: usize) {
+            self.failing.lock().insert(id);
+        }
+
+        pub fn sent(&self) -> Vec<SentCommand> {
+            self.sent.lock().clone()
+        }
+
+        fn record(&self, target: Target, command: &CommandLight) {
+            for light in self.lights.lock().iter_mut() {
+                if target == Target::Light(light.id) || target == Target::Group(0) {
+                    let state = &mut light.light.state;
+                    state.on = command.on.unwrap_or(state.on);
+                    state.bri = command.bri.or(state.bri);
+                    state.hue = command.hue.or(state.hue);
+                    state.sat = command.sat.or(state.sat);
+                    state.ct = command.ct.or(state.ct);
+                    state.xy = command.xy.or(state.xy);
+                }
+            }
+            self.sent.lock().push(SentCommand { target, command: command.clone(), at: Instant::now() });
+        }
+    }
+
+    impl LightController for MockController {
+        fn get_lights(&self) -> Result<Vec<IdentifiedLight>, ControllerError> {
+            Ok(self.lights.lock().clone())
+        }
+
+        /// None, as on a bridge without rooms; group 0, which every bridge has, is never listed.
+        fn get_groups(&self) -> Result<Vec<IdentifiedGroup>, ControllerError> {
+            Ok(Vec::new())
+        }
+
+        fn set_light(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+            if self.failing.lock().contains(&id) {
+                self.lights.lock().retain(|light| light.id != id);
+                let msg = format!("resource, /lights/{}, not available", id);
+                return Err(HueError::BridgeError { code: 3, msg }.into());
+            }
+            self.record(Target::Light(id), command);
+            Ok(())
+        }
+
+        fn set_group(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+            self.record(Target::Group(id), command);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Posts a summary of handled events to a Discord channel through a webhook.
+
+use std::future::Future;
+use std::time::Duration;
+
+use log::{debug, warn};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::DiscordConfig;
+
+// Embed color when no effect played
+const DEFAULT_COLOR: u32 = 0x5865f2;
+// Embeds waiting to be posted before new ones are dropped
+const QUEUE_CAPACITY: usize = 32;
+// Times an embed is retried after being rate limited before it is given up on
+const RATE_LIMIT_RETRIES: u32 = 3;
+// Wait when a rate limited response doesn't say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+// Longest wait honored, so a bogus `retry_after` can't stall the queue
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+pub struct DiscordNotifier {
+    sender: mpsc::Sender<Value>,
+    event_types: Vec<String>,
+}
+
+impl DiscordNotifier {
+    /// The notifier and the task that posts its embeds one by one, which has to be spawned for
+    /// anything to be sent. The task ends once the notifier is dropped and the queue is empty.
+    pub fn new(config: &DiscordConfig) -> (Self, impl Future<Output = ()>) {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let notifier = Self {
+            sender,
+            event_types: config.event_types.clone(),
+        };
+        (notifier, post_embeds(config.webhook_url.clone(), receiver))
+    }
+
+    /// Queues an embed for an event message (the one published to MQTT) if its type is wanted,
+    /// without waiting; while Discord is slow or rate limiting, embeds beyond the queue's
+    /// capacity are dropped.
+    pub fn notify(&self, message: &Value) {
+        let event_type = message["event_type"].as_str().unwrap_or_default();
+        if !self.event_types.is_empty() && !self.event_types.iter().any(|wanted| wanted == event_type) {
+            return;
+        }
+        match self.sender.try_send(embed(message)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Dropping Discord notification for {}: the queue is full", event_type),
+            // The posting task is gone, at shutdown
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+async fn post_embeds(webhook_url: String, mut receiver: mpsc::Receiver<Value>) {
+    let client = reqwest::Client::new();
+    while let Some(embed) = receiver.recv().await {
+        let body = json!({ "embeds": [embed] }).to_string();
+        for attempt in 0..=RATE_LIMIT_RETRIES {
+            let request = client.post(&webhook_url)
+                .header("Content-Type", "application/json")
+                .body(body.clone());
+            match request.send().await {
+                Ok(response) if response.status().is_success() => debug!("Posted event to Discord"),
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS && attempt < RATE_LIMIT_RETRIES => {
+                    let header = response.headers().get("Retry-After")
+                        .and_then(|value| value.to_str().ok())
+                        .map(str::to_string);
+                    let delay = retry_after(header.as_deref(), &response.text().await.unwrap_or_default());
+                    debug!("Discord rate limited the webhook, retrying in {:?}", delay);
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    warn!("Discord webhook rejected the event ({}): {}", status, body);
+                }
+                Err(e) => warn!("Failed to post event to Discord: {}", e),
+            }
+            break;
+        }
+    }
+}
+
+/// How long a 429 asks to wait, from the `retry_after` seconds in its JSON body, which carry
+/// fractions, or else its `Retry-After` header, capped at `MAX_RETRY_AFTER`.
+fn retry_after(header: Option<&str>, body: &str) -> Duration {
+    let from_body = serde_json::from_str::<Value>(body).ok().and_then(|body| body["retry_after"].as_f64());
+    let seconds: Option<f64> = from_body.or_else(|| header?.trim().parse().ok());
+    match seconds.map(Duration::try_from_secs_f64) {
+        Some(Ok(wait)) => wait.min(MAX_RETRY_AFTER),
+        // Too large to be a duration at all
+        Some(Err(_)) if seconds.is_some_and(|seconds| seconds > 0.0) => MAX_RETRY_AFTER,
+        _ => DEFAULT_RETRY_AFTER,
+    }
+}
+
+/// A Discord embed summarizing an event message, in the color of the effect it played.
+fn embed(message: &Value) -> Value {
+    let text = |key: &str| message[key].as_str().map(str::to_string);
+    let event_type = text("event_type").unwrap_or_default();
+
+    let mut fields = Vec::new();
+    if let Some(name) = text("name") {
+        fields.push(json!({ "name": "From", "value": name, "inline": true }));
+    }
+    if let Some(amount) = message["amount"].as_f64() {
+        let value = match text("currency") {
+            Some(currency) => format!("{} {}", amount, currency),
+            None => amount.to_string(),
+        };
+        fields.push(json!({ "name": "Amount", "value": value, "inline": true }));
+    }
+    let effect = &message["effect"];
+    let effect_text = match (effect["color"].as_str(), message["tier"].as_f64()) {
+        (Some(color), Some(tier)) => format!("{} (tier {})", color, tier),
+        (Some(color), None) => color.to_string(),
+        (None, _) => "None".to_string(),
+    };
+    fields.push(json!({ "name": "Effect", "value": effect_text, "inline": true }));
+    let color = effect["color"].as_str()
+        .and_then(|color| color.strip_prefix('#').filter(|hex| hex.len() == 6))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .unwrap_or(DEFAULT_COLOR);
+
+    let title = match text("platform") {
+        Some(platform) => format!("{} ({})", event_type, platform),
+        None => event_type,
+    };
+    json!({
+        "title": title,
+        "color": color,
+        "fields": fields,
+        "timestamp": message["timestamp"],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_wait_comes_from_the_body_then_the_header() {
+        assert_eq!(retry_after(Some("2"), r#"{"message": "You are being rate limited.", "retry_after": 0.25}"#), Duration::from_millis(250));
+        assert_eq!(retry_after(Some("2"), ""), Duration::from_secs(2));
+        assert_eq!(retry_after(None, r#"{"retry_after": -1}"#), DEFAULT_RETRY_AFTER);
+        assert_eq!(retry_after(Some("soon"), "rate limited"), DEFAULT_RETRY_AFTER);
+    }
+
+    #[test]
+    fn rate_limit_wait_is_capped() {
+        assert_eq!(retry_after(None, r#"{"retry_after": 600}"#), MAX_RETRY_AFTER);
+        assert_eq!(retry_after(Some("86400"), ""), MAX_RETRY_AFTER);
+        assert_eq!(retry_after(None, r#"{"retry_after": 60.0}"#), MAX_RETRY_AFTER);
+        // Beyond what a Duration holds
+        assert_eq!(retry_after(None, r#"{"retry_after": 1e30}"#), MAX_RETRY_AFTER);
+        assert_eq!(retry_after(Some("1e300"), ""), MAX_RETRY_AFTER);
+    }
+}
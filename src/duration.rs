@@ -0,0 +1,114 @@
+//! Serde helpers for duration fields that accept either milliseconds or
+//! human-readable strings such as `"500ms"`, `"5s"`, `"1.5m"` or `"1h"`.
+
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDuration {
+    Millis(u64),
+    Text(String),
+}
+
+impl RawDuration {
+    fn into_millis(self) -> Result<u64, String> {
+        match self {
+            RawDuration::Millis(ms) => Ok(ms),
+            RawDuration::Text(text) => parse_millis(&text),
+        }
+    }
+}
+
+/// Parses a duration string into milliseconds; a bare number is taken as milliseconds.
+pub fn parse_millis(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {:?}", text))?;
+    let scale = match unit.trim() {
+        "" | "ms" => 1.0,
+        "s" => 1_000.0,
+        "m" => 60_000.0,
+        "h" => 3_600_000.0,
+        other => {
+            return Err(format!(
+                "Invalid duration unit {:?} in {:?}, expected ms, s, m or h",
+                other, text
+            ))
+        }
+    };
+
+    let millis = (value * scale).round();
+    if millis > u64::MAX as f64 {
+        return Err(format!("Duration {:?} is too long", text));
+    }
+    Ok(millis as u64)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    RawDuration::deserialize(deserializer)?
+        .into_millis()
+        .map_err(serde::de::Error::custom)
+}
+
+pub fn deserialize_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    Option::<RawDuration>::deserialize(deserializer)?
+        .map(RawDuration::into_millis)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Durations {
+        #[serde(deserialize_with = "deserialize")]
+        duration: u64,
+        #[serde(default, deserialize_with = "deserialize_option")]
+        optional: Option<u64>,
+    }
+
+    fn durations(json: &str) -> Result<Durations, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn units_and_bare_numbers_parse_to_millis() {
+        assert_eq!(parse_millis("1500"), Ok(1500));
+        assert_eq!(parse_millis("250ms"), Ok(250));
+        assert_eq!(parse_millis("1.5s"), Ok(1500));
+        assert_eq!(parse_millis("2m"), Ok(120_000));
+        assert_eq!(parse_millis("1h"), Ok(3_600_000));
+        assert_eq!(parse_millis("  5 s "), Ok(5000));
+    }
+
+    #[test]
+    fn negative_unknown_and_overflowing_durations_are_rejected() {
+        for text in ["-1s", "1x", "s", "", "1.2.3s", "99999999999999999999999h"] {
+            assert!(parse_millis(text).is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn fields_take_numbers_or_strings() {
+        let parsed = durations(r#"{ "duration": 1500, "optional": "2s" }"#).unwrap();
+        assert_eq!((parsed.duration, parsed.optional), (1500, Some(2000)));
+        let parsed = durations(r#"{ "duration": "250ms" }"#).unwrap();
+        assert_eq!((parsed.duration, parsed.optional), (250, None));
+        let parsed = durations(r#"{ "duration": "1m", "optional": null }"#).unwrap();
+        assert_eq!((parsed.duration, parsed.optional), (60_000, None));
+
+        assert!(durations(r#"{ "duration": -1 }"#).is_err());
+        assert!(durations(r#"{ "duration": "1x" }"#).is_err());
+        assert!(durations(r#"{ "duration": 1, "optional": "-1s" }"#).is_err());
+    }
+}
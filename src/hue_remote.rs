@@ -0,0 +1,381 @@
+//! The Hue bridge reached through the Hue Remote API (api.meethue.com) instead of the LAN, for
+//! when LumiaLive runs somewhere the bridge can't be discovered.
+//!
+//! Requests carry an OAuth access token, refreshed from the refresh token shortly before it
+//! expires. Hue replaces the refresh token on every refresh, so the current pair is kept in
+//! `hue.remote.token_file` and read back on the next start. The remote endpoint takes the same
+//! CLIP v1 requests as the bridge itself under `/route/api/<username>`.
+//!
+//! The controller is called from inside the async runtime, where reqwest's blocking client
+//! refuses to run, so requests are handed to a thread of their own and waited on.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use hueclient::{CommandLight, Group, HueError, IdentifiedGroup, IdentifiedLight, Light};
+use log::{debug, info, warn};
+use parking_lot::Mutex;
+use reqwest::blocking::Client;
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::controller::{ControllerError, LightController};
+use crate::{check_command_response, response_error, AppError, HueRemoteConfig};
+
+const API_URL: &str = "https://api.meethue.com";
+// Access tokens are refreshed this long before they expire
+const REFRESH_MARGIN_SECS: i64 = 300;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce(&Client) + Send>;
+
+pub struct HueRemoteController {
+    jobs: mpsc::Sender<Job>,
+    api_url: String,
+    client_id: String,
+    client_secret: String,
+    token_file: String,
+    username: String,
+    tokens: Mutex<Tokens>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Tokens {
+    access_token: String,
+    refresh_token: String,
+    // Unix time in seconds
+    expires_at: i64,
+}
+
+impl HueRemoteController {
+    /// Connects as `username` and checks the bridge answers through the remote endpoint.
+    pub fn connect(config: &HueRemoteConfig, username: &str) -> Result<Self, AppError> {
+        info!("Connecting to the Hue bridge through the Hue Remote API...");
+        let controller = Self::new(config, username, API_URL)?;
+        let lights = controller.get_lights()
+            .map_err(|e| AppError::Bridge(format!("Failed to get lights through the Hue Remote API: {}", e)))?;
+        info!("Successfully connected through the Hue Remote API. Found {} lights", lights.len());
+        Ok(controller)
+    }
+
+    /// Has the bridge create a user for `device_type`, pressing its link button remotely, and
+    /// returns the new username.
+    pub fn register_user(config: &HueRemoteConfig, device_type: &str) -> Result<String, AppError> {
+        Self::new(config, "", API_URL)?.register(device_type)
+    }
+
+    fn new(config: &HueRemoteConfig, username: &str, api_url: &str) -> Result<Self, AppError> {
+        if config.client_id.is_empty() || config.client_secret.is_empty() {
+            return Err(AppError::Config("hue.remote needs the client_id and client_secret of a Hue developer app".to_string()));
+        }
+        let tokens = load_tokens(config)?;
+
+        let (jobs, queue) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name("hue-remote".to_string())
+            .spawn(move || {
+                let client = Client::new();
+                // Ends once the controller, which holds the only sender, is dropped
+                for job in queue {
+                    job(&client);
+                }
+            })?;
+
+        Ok(Self {
+            jobs,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            client_id: config.client_id.clone(),
+            client_secret: config.client_secret.clone(),
+            token_file: config.token_file.clone(),
+            username: username.to_string(),
+            tokens: Mutex::new(tokens),
+        })
+    }
+
+    fn register(&self, device_type: &str) -> Result<String, AppError> {
+        let remote_error = |e: ControllerError| AppError::Bridge(format!("Remote pairing failed: {}", e));
+        let pressed = self.request(Method::PUT, "/route/api/0/config", Some(json!({ "linkbutton": true }))).map_err(remote_error)?;
+        if let Some(e) = response_error(&pressed) {
+            return Err(remote_error(e.into()));
+        }
+        let created = self.request(Method::POST, "/route/api", Some(json!({ "devicetype": device_type }))).map_err(remote_error)?;
+        if let Some(e) = response_error(&created) {
+            return Err(remote_error(e.into()));
+        }
+        created[0]["success"]["username"].as_str()
+            .map(str::to_string)
+            .ok_or_else(|| AppError::Bridge(format!("Unexpected response to remote pairing: {}", created)))
+    }
+
+    /// Runs `job` on the request thread and waits for its result.
+    fn run<T: Send + 'static>(&self, job: impl FnOnce(&Client) -> T + Send + 'static) -> Result<T, ControllerError> {
+        let (result_tx, result_rx) = mpsc::channel();
+        self.jobs.send(Box::new(move |client| {
+            let _ = result_tx.send(job(client));
+        })).map_err(|_| remote_error("the request thread stopped"))?;
+        result_rx.recv().map_err(|_| remote_error("the request thread stopped"))
+    }
+
+    /// A request to the bridge's CLIP v1 API, under this controller's username.
+    fn bridge(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value, ControllerError> {
+        self.request(method, &format!("/route/api/{}{}", self.username, path), body)
+    }
+
+    /// Sends a request with the current access token, refreshing it once if it was rejected.
+    fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value, ControllerError> {
+        let url = format!("{}{}", self.api_url, path);
+        let body = body.map(|body| body.to_string());
+        let mut token = self.access_token()?;
+        let mut refreshed = false;
+        loop {
+            let (method, url, body, bearer) = (method.clone(), url.clone(), body.clone(), token.clone());
+            let (status, text) = self.run(move |client| {
+                let mut request = client.request(method, url).bearer_auth(bearer).timeout(REQUEST_TIMEOUT);
+                if let Some(body) = body {
+                    request = request.header("Content-Type", "application/json").body(body);
+                }
+                let response = request.send()?;
+                Ok::<_, reqwest::Error>((response.status(), response.text()?))
+            })?.map_err(remote_error)?;
+
+            match status {
+                StatusCode::UNAUTHORIZED if !refreshed => {
+                    warn!("Hue Remote API rejected the access token, refreshing it");
+                    let refresh_token = self.tokens.lock().refresh_token.clone();
+                    token = self.refresh(&refresh_token)?;
+                    refreshed = true;
+                }
+                status if status.is_success() => {
+                    return serde_json::from_str(&text).map_err(|e| remote_error(format!("unreadable response: {}", e)));
+                }
+                status => return Err(remote_error(format!("{} {}", status, text.trim()))),
+            }
+        }
+    }
+
+    fn access_token(&self) -> Result<String, ControllerError> {
+        let tokens = self.tokens.lock().clone();
+        if tokens.expires_at - REFRESH_MARGIN_SECS > Utc::now().timestamp() {
+            return Ok(tokens.access_token);
+        }
+        self.refresh(&tokens.refresh_token)
+    }
+
+    /// Trades `refresh_token` for a new token pair, saving it to the token file.
+    fn refresh(&self, refresh_token: &str) -> Result<String, ControllerError> {
+        debug!("Refreshing the Hue Remote API access token");
+        let url = format!("{}/v2/oauth2/token", self.api_url);
+        let (client_id, client_secret) = (self.client_id.clone(), self.client_secret.clone());
+        let form = [("grant_type", "refresh_token".to_string()), ("refresh_token", refresh_token.to_string())];
+        let (status, text) = self.run(move |client| {
+            let response = client.post(url)
+                .basic_auth(client_id, Some(client_secret))
+                .form(&form)
+                .timeout(REQUEST_TIMEOUT)
+                .send()?;
+            Ok::<_, reqwest::Error>((response.status(), response.text()?))
+        })?.map_err(remote_error)?;
+        if !status.is_success() {
+            return Err(remote_error(format!("refreshing the access token failed ({}): {}", status, text.trim())));
+        }
+
+        let tokens = parse_tokens(&text, refresh_token, Utc::now().timestamp())
+            .ok_or_else(|| remote_error(format!("unexpected token response: {}", text)))?;
+        if let Err(e) = save_tokens(&self.token_file, &tokens) {
+            warn!("Failed to save the Hue Remote API tokens to {}: {}", self.token_file, e);
+        }
+        info!("Refreshed the Hue Remote API access token");
+        let access_token = tokens.access_token.clone();
+        *self.tokens.lock() = tokens;
+        Ok(access_token)
+    }
+}
+
+impl LightController for HueRemoteController {
+    fn get_lights(&self) -> Result<Vec<IdentifiedLight>, ControllerError> {
+        let lights = by_id::<Light>(self.bridge(Method::GET, "/lights", None)?)?;
+        Ok(lights.into_iter().map(|(id, light)| IdentifiedLight { id, light }).collect())
+    }
+
+    fn get_groups(&self) -> Result<Vec<IdentifiedGroup>, ControllerError> {
+        let groups = by_id::<Group>(self.bridge(Method::GET, "/groups", None)?)?;
+        Ok(groups.into_iter().map(|(id, group)| IdentifiedGroup { id, group }).collect())
+    }
+
+    fn set_light(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        let response = self.bridge(Method::PUT, &format!("/lights/{}/state", id), Some(command_body(command)?))?;
+        check_command_response(response, "light", id)
+    }
+
+    fn set_group(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        let response = self.bridge(Method::PUT, &format!("/groups/{}/action", id), Some(command_body(command)?))?;
+        check_command_response(response, "group", id)
+    }
+}
+
+fn command_body(command: &CommandLight) -> Result<Value, ControllerError> {
+    serde_json::to_value(command).map_err(|e| Box::new(HueError::from(e)))
+}
+
+/// Reads a `{"<id>": {...}}` listing, sorted by id. An error list instead fails the call.
+fn by_id<T: DeserializeOwned>(response: Value) -> Result<Vec<(usize, T)>, ControllerError> {
+    if let Some(e) = response_error(&response) {
+        return Err(e.into());
+    }
+    let entries: HashMap<String, T> = serde_json::from_value(response).map_err(HueError::from)?;
+    let mut entries = entries.into_iter()
+        .map(|(id, entry)| id.parse().map(|id| (id, entry)).map_err(|_| remote_error(format!("non-numeric id {:?}", id))))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|(id, _)| *id);
+    Ok(entries)
+}
+
+/// The token pair in an OAuth token response received at `now`. Hue always sends a new refresh
+/// token, but the old one is kept if a response leaves it out.
+fn parse_tokens(response: &str, refresh_token: &str, now: i64) -> Option<Tokens> {
+    let response: Value = serde_json::from_str(response).ok()?;
+    let expires_in = match &response["expires_in"] {
+        Value::String(seconds) => seconds.parse().ok()?,
+        seconds => seconds.as_i64()?,
+    };
+    Some(Tokens {
+        access_token: response["access_token"].as_str()?.to_string(),
+        refresh_token: response["refresh_token"].as_str().unwrap_or(refresh_token).to_string(),
+        expires_at: now + expires_in,
+    })
+}
+
+/// The tokens saved by the last refresh, or the configured refresh token on the first start.
+fn load_tokens(config: &HueRemoteConfig) -> Result<Tokens, AppError> {
+    if Path::new(&config.token_file).exists() {
+        debug!("Reading Hue Remote API tokens from {}", config.token_file);
+        return Ok(serde_json::from_str(&std::fs::read_to_string(&config.token_file)?)?);
+    }
+    if config.refresh_token.is_empty() {
+        return Err(AppError::Config(format!(
+            "hue.remote needs a refresh_token, or a token file at {}", config.token_file
+        )));
+    }
+    Ok(Tokens {
+        access_token: String::new(),
+        refresh_token: config.refresh_token.clone(),
+        expires_at: 0,
+    })
+}
+
+fn save_tokens(path: &str, tokens: &Tokens) -> Result<(), AppError> {
+    std::fs::write(path, serde_json::to_string_pretty(tokens)?)?;
+    Ok(())
+}
+
+fn remote_error(e: impl std::fmt::Display) -> ControllerError {
+    HueError::ProtocolError { msg: format!("Hue Remote API: {}", e) }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn token_response_gives_the_expiry_and_new_refresh_token() {
+        let response = r#"{"access_token": "new-access", "expires_in": 604800, "refresh_token": "new-refresh", "token_type": "bearer"}"#;
+        let tokens = parse_tokens(response, "old-refresh", 1_000).unwrap();
+        assert_eq!(tokens, Tokens {
+            access_token: "new-access".to_string(),
+            refresh_token: "new-refresh".to_string(),
+            expires_at: 605_800,
+        });
+
+        // Hue has sent the expiry as a string; a missing refresh token keeps the old one
+        let tokens = parse_tokens(r#"{"access_token": "a", "expires_in": "60"}"#, "old-refresh", 0).unwrap();
+        assert_eq!((tokens.refresh_token.as_str(), tokens.expires_at), ("old-refresh", 60));
+        assert_eq!(parse_tokens(r#"{"error": "invalid_grant"}"#, "old-refresh", 0), None);
+    }
+
+    #[test]
+    fn light_listing_is_sorted_by_id_and_errors_fail() {
+        let light = json!({ "name": "Desk", "modelid": "LCT015", "swversion": "1", "uniqueid": "00:17",
+                            "state": { "on": true, "bri": 254 } });
+        let lights = by_id::<Light>(json!({ "10": light, "2": light })).unwrap();
+        assert_eq!(lights.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [2, 10]);
+
+        let denied = json!([{ "error": { "type": 1, "address": "/lights", "description": "unauthorized user" } }]);
+        let Err(e) = by_id::<Light>(denied) else {
+            panic!("an error list should fail");
+        };
+        assert!(matches!(*e, HueError::BridgeError { code: 1, .. }));
+    }
+
+    /// Answers `responses` in order, one per connection, and returns each request's first line
+    /// and Authorization header.
+    fn serve(responses: Vec<(u16, Value)>) -> (String, thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            responses.into_iter().map(|(status, body)| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let (mut authorization, mut length) = (String::new(), 0);
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_once(": ").unwrap();
+                    match name.to_ascii_lowercase().as_str() {
+                        "authorization" => authorization = value.to_string(),
+                        "content-length" => length = value.parse().unwrap(),
+                        _ => {}
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                let body = body.to_string();
+                write!(reader.get_mut(), "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                       status, body.len(), body).unwrap();
+                (request_line.trim_end().to_string(), authorization)
+            }).collect()
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn expired_token_is_refreshed_and_saved_before_the_request() {
+        let token_file = std::env::temp_dir().join(format!("lumialive-hue-remote-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&token_file);
+        let config = HueRemoteConfig {
+            enabled: true,
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            refresh_token: "first-refresh".to_string(),
+            token_file: token_file.to_string_lossy().into_owned(),
+        };
+        let (url, server) = serve(vec![
+            (200, json!({ "access_token": "access", "expires_in": 3600, "refresh_token": "second-refresh" })),
+            (200, json!([{ "success": { "/lights/3/state/on": true } }])),
+        ]);
+
+        let controller = HueRemoteController::new(&config, "user", &url).unwrap();
+        controller.set_light(3, &CommandLight::default().on()).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].0, "POST /v2/oauth2/token HTTP/1.1");
+        assert!(requests[0].1.starts_with("Basic "), "{:?}", requests[0]);
+        assert_eq!(requests[1], ("PUT /route/api/user/lights/3/state HTTP/1.1".to_string(), "Bearer access".to_string()));
+        let saved: Tokens = serde_json::from_str(&std::fs::read_to_string(&token_file).unwrap()).unwrap();
+        assert_eq!(saved.refresh_token, "second-refresh");
+        std::fs::remove_file(&token_file).unwrap();
+    }
+}
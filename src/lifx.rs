@@ -0,0 +1,395 @@
+//! LIFX bulbs over the LAN protocol (UDP port 56700), as an alternative to the Hue bridge.
+//!
+//! Bulbs are found by broadcasting a state request and numbered from 1 in the order they
+//! answered the first time, sorted by MAC address; later arrivals are numbered after them.
+//! Hue's units are mapped onto LIFX's HSBK: hue carries over as is, saturation and brightness
+//! are scaled from 0-254 to 0-65535, and color temperatures become kelvin.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use hueclient::{CommandLight, HueError, IdentifiedGroup, IdentifiedLight};
+use log::{debug, info};
+use parking_lot::Mutex;
+
+use crate::controller::{ControllerError, LightController};
+use crate::{AppError, LifxConfig};
+
+const HEADER_LEN: usize = 36;
+// Message types
+const LIGHT_GET: u16 = 101;
+const LIGHT_SET_COLOR: u16 = 102;
+const LIGHT_SET_WAVEFORM: u16 = 103;
+const LIGHT_STATE: u16 = 107;
+const LIGHT_SET_POWER: u16 = 117;
+// Waveform used for Hue's select/lselect breathe
+const WAVEFORM_SINE: u8 = 1;
+const BREATHE_PERIOD_MS: u32 = 1000;
+// Kelvin range LIFX bulbs accept
+const MIN_KELVIN: u32 = 1500;
+const MAX_KELVIN: u32 = 9000;
+const DEFAULT_KELVIN: u16 = 3500;
+
+#[derive(Debug, Clone, Copy)]
+struct Hsbk {
+    hue: u16,
+    saturation: u16,
+    brightness: u16,
+    kelvin: u16,
+}
+
+impl Hsbk {
+    fn to_bytes(self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[0..2].copy_from_slice(&self.hue.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.saturation.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.brightness.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.kelvin.to_le_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bulb {
+    target: u64,
+    addr: SocketAddr,
+    label: String,
+    color: Hsbk,
+    on: bool,
+}
+
+pub struct LifxController {
+    socket: UdpSocket,
+    broadcast: SocketAddr,
+    timeout: Duration,
+    // Identifies our packets to the bulbs
+    source: u32,
+    sequence: AtomicU8,
+    // Index + 1 is the light id
+    bulbs: Mutex<Vec<Bulb>>,
+}
+
+impl LifxController {
+    /// Broadcasts for bulbs and waits `timeout_ms` for them to answer.
+    pub fn discover(config: &LifxConfig) -> Result<Self, AppError> {
+        let broadcast = config.broadcast.to_socket_addrs()?.next()
+            .ok_or_else(|| AppError::Config(format!("Invalid lifx.broadcast {:?}", config.broadcast)))?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_broadcast(true)?;
+        let controller = Self {
+            socket,
+            broadcast,
+            timeout: Duration::from_millis(config.timeout_ms),
+            source: rand::random::<u32>().max(2),
+            sequence: AtomicU8::new(0),
+            bulbs: Mutex::new(Vec::new()),
+        };
+
+        info!("Discovering LIFX bulbs via {}...", broadcast);
+        controller.refresh()?;
+        let bulbs = controller.bulbs.lock();
+        if bulbs.is_empty() {
+            return Err(AppError::Bridge(format!("No LIFX bulbs answered on {}", broadcast)));
+        }
+        for (index, bulb) in bulbs.iter().enumerate() {
+            info!("LIFX light {}: {:?} at {}", index + 1, bulb.label, bulb.addr);
+        }
+        drop(bulbs);
+        Ok(controller)
+    }
+
+    /// Asks every bulb for its state, returning once all known bulbs have answered or the
+    /// timeout passes. Bulbs answering for the first time are added to the end of the list.
+    fn refresh(&self) -> std::io::Result<()> {
+        self.socket.set_nonblocking(true)?;
+        let mut stale = [0; 128];
+        while self.socket.recv_from(&mut stale).is_ok() {}
+        self.socket.set_nonblocking(false)?;
+
+        self.send(0, self.broadcast, LIGHT_GET, &[])?;
+        let known: Vec<(u64, SocketAddr)> = self.bulbs.lock().iter().map(|bulb| (bulb.target, bulb.addr)).collect();
+        for (target, addr) in &known {
+            self.send(*target, *addr, LIGHT_GET, &[])?;
+        }
+
+        let first_discovery = known.is_empty();
+        // Each bulb may answer both the broadcast and its own request
+        let mut found = Vec::new();
+        let mut answered = 0;
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0; 128];
+        while first_discovery || answered < known.len() {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) else {
+                break;
+            };
+            self.socket.set_read_timeout(Some(remaining))?;
+            let (len, addr) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e),
+            };
+            let Some(bulb) = parse_state(&buf[..len], addr) else {
+                continue;
+            };
+
+            if !found.contains(&bulb.target) {
+                found.push(bulb.target);
+                answered += 1;
+            }
+            let mut bulbs = self.bulbs.lock();
+            match bulbs.iter_mut().find(|known| known.target == bulb.target) {
+                Some(known) => *known = bulb,
+                None => {
+                    if !first_discovery {
+                        info!("Found new LIFX light {}: {:?} at {}", bulbs.len() + 1, bulb.label, bulb.addr);
+                    }
+                    bulbs.push(bulb);
+                }
+            }
+        }
+        if first_discovery {
+            self.bulbs.lock().sort_by_key(|bulb| bulb.target);
+        }
+        Ok(())
+    }
+
+    fn send(&self, target: u64, addr: SocketAddr, kind: u16, payload: &[u8]) -> std::io::Result<()> {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        self.socket.send_to(&packet(self.source, target, sequence, kind, payload), addr)?;
+        Ok(())
+    }
+
+    fn send_color(&self, bulb: &Bulb, color: Hsbk, duration_ms: u32) -> std::io::Result<()> {
+        let mut payload = vec![0];
+        payload.extend(color.to_bytes());
+        payload.extend(duration_ms.to_le_bytes());
+        self.send(bulb.target, bulb.addr, LIGHT_SET_COLOR, &payload)
+    }
+
+    fn send_power(&self, bulb: &Bulb, on: bool, duration_ms: u32) -> std::io::Result<()> {
+        let level: u16 = if on { u16::MAX } else { 0 };
+        let mut payload = level.to_le_bytes().to_vec();
+        payload.extend(duration_ms.to_le_bytes());
+        self.send(bulb.target, bulb.addr, LIGHT_SET_POWER, &payload)
+    }
+
+    /// Breathes down to a quarter of `color`'s brightness and back, `cycles` times.
+    fn send_breathe(&self, bulb: &Bulb, color: Hsbk, cycles: f32) -> std::io::Result<()> {
+        let dimmed = Hsbk { brightness: color.brightness / 4, ..color };
+        let mut payload = vec![0, 1];
+        payload.extend(dimmed.to_bytes());
+        payload.extend(BREATHE_PERIOD_MS.to_le_bytes());
+        payload.extend(cycles.to_le_bytes());
+        payload.extend(0i16.to_le_bytes());
+        payload.push(WAVEFORM_SINE);
+        self.send(bulb.target, bulb.addr, LIGHT_SET_WAVEFORM, &payload)
+    }
+
+    fn apply(&self, bulb: &mut Bulb, command: &CommandLight) -> std::io::Result<()> {
+        let duration_ms = command.transitiontime.map_or(0, |time| time as u32 * 100);
+        let mut color = bulb.color;
+        if let Some(hue) = command.hue {
+            color.hue = hue;
+        }
+        if let Some(sat) = command.sat {
+            color.saturation = to_lifx(sat);
+        }
+        if let Some(bri) = command.bri {
+            color.brightness = to_lifx(bri);
+        }
+        if let Some(mirek) = command.ct {
+            color.kelvin = (1_000_000 / mirek.max(1) as u32).clamp(MIN_KELVIN, MAX_KELVIN) as u16;
+            color.saturation = 0;
+        }
+        if command.xy.is_some() && command.hue.is_none() {
+            debug!("LIFX light {:?} ignores xy colors, use the hue/saturation color mode", bulb.label);
+        }
+
+        if command.on == Some(false) {
+            self.send_power(bulb, false, duration_ms)?;
+            bulb.on = false;
+            return Ok(());
+        }
+        if command.hue.is_some() || command.sat.is_some() || command.bri.is_some() || command.ct.is_some() {
+            self.send_color(bulb, color, duration_ms)?;
+            bulb.color = color;
+        }
+        if command.on == Some(true) && !bulb.on {
+            self.send_power(bulb, true, duration_ms)?;
+            bulb.on = true;
+        }
+        match command.alert.as_deref() {
+            Some("select") => self.send_breathe(bulb, color, 1.0),
+            Some("lselect") => self.send_breathe(bulb, color, (crate::LSELECT_CYCLE_MS / BREATHE_PERIOD_MS as u64) as f32),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl LightController for LifxController {
+    fn get_lights(&self) -> Result<Vec<IdentifiedLight>, ControllerError> {
+        self.refresh().map_err(protocol_error)?;
+        Ok(self.bulbs.lock().iter().enumerate().map(|(index, bulb)| IdentifiedLight {
+            id: index + 1,
+            light: hueclient::Light {
+                name: bulb.label.clone(),
+                modelid: String::new(),
+                swversion: String::new(),
+                uniqueid: format!("{:012x}", bulb.target),
+                state: hueclient::LightState {
+                    on: bulb.on,
+                    bri: Some(to_hue(bulb.color.brightness)),
+                    hue: Some(bulb.color.hue),
+                    sat: Some(to_hue(bulb.color.saturation)),
+                    ct: None,
+                    xy: None,
+                },
+            },
+        }).collect())
+    }
+
+    /// LIFX has no bridge groups; group 0 still means every light.
+    fn get_groups(&self) -> Result<Vec<IdentifiedGroup>, ControllerError> {
+        Ok(Vec::new())
+    }
+
+    fn set_light(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        let mut bulbs = self.bulbs.lock();
+        let bulb = id.checked_sub(1).and_then(|index| bulbs.get_mut(index))
+            .ok_or_else(|| HueError::ProtocolError { msg: format!("No LIFX light {}", id) })?;
+        self.apply(bulb, command).map_err(protocol_error)
+    }
+
+    fn set_group(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        if id != 0 {
+            return Err(HueError::ProtocolError { msg: format!("LIFX has no group {}", id) }.into());
+        }
+        for bulb in self.bulbs.lock().iter_mut() {
+            self.apply(bulb, command).map_err(protocol_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn protocol_error(e: std::io::Error) -> ControllerError {
+    HueError::ProtocolError { msg: format!("LIFX: {}", e) }.into()
+}
+
+fn to_lifx(value: u8) -> u16 {
+    (value.min(254) as u32 * u16::MAX as u32 / 254) as u16
+}
+
+fn to_hue(value: u16) -> u8 {
+    (value as u32 * 254 / u16::MAX as u32) as u8
+}
+
+/// A LAN protocol packet; a `target` of 0 addresses every bulb.
+fn packet(source: u32, target: u64, sequence: u8, kind: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.extend(((HEADER_LEN + payload.len()) as u16).to_le_bytes());
+    // Protocol 1024, addressable, and tagged when sent to every bulb
+    let tagged = if target == 0 { 1 << 13 } else { 0 };
+    packet.extend((1024u16 | 1 << 12 | tagged).to_le_bytes());
+    packet.extend(source.to_le_bytes());
+    packet.extend(target.to_le_bytes());
+    packet.extend([0; 6]);
+    // Neither an acknowledgement nor a response is wanted
+    packet.push(0);
+    packet.push(sequence);
+    packet.extend([0; 8]);
+    packet.extend(kind.to_le_bytes());
+    packet.extend([0; 2]);
+    packet.extend(payload);
+    packet
+}
+
+/// Reads a `LightState` reply into a bulb.
+fn parse_state(packet: &[u8], addr: SocketAddr) -> Option<Bulb> {
+    let u16_at = |offset: usize| packet.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+    if u16_at(32)? != LIGHT_STATE || packet.len() < HEADER_LEN + 44 {
+        return None;
+    }
+    let target = u64::from_le_bytes(packet[8..16].try_into().ok()?);
+    let label = &packet[HEADER_LEN + 12..HEADER_LEN + 44];
+    let label = String::from_utf8_lossy(&label[..label.iter().position(|&b| b == 0).unwrap_or(label.len())]).into_owned();
+    Some(Bulb {
+        target,
+        addr,
+        label,
+        color: Hsbk {
+            hue: u16_at(HEADER_LEN)?,
+            saturation: u16_at(HEADER_LEN + 2)?,
+            brightness: u16_at(HEADER_LEN + 4)?,
+            kelvin: u16_at(HEADER_LEN + 6).filter(|&kelvin| kelvin != 0).unwrap_or(DEFAULT_KELVIN),
+        },
+        on: u16_at(HEADER_LEN + 10)? > 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        "192.168.1.50:56700".parse().unwrap()
+    }
+
+    /// A `LightState` payload for a powered bulb labelled "Desk".
+    fn light_state() -> Vec<u8> {
+        let color = Hsbk { hue: 1000, saturation: 2000, brightness: 3000, kelvin: 0 };
+        let mut payload = color.to_bytes().to_vec();
+        payload.extend([0; 2]);
+        payload.extend(u16::MAX.to_le_bytes());
+        let mut label = [0; 32];
+        label[..4].copy_from_slice(b"Desk");
+        payload.extend(label);
+        payload.extend([0; 8]);
+        payload
+    }
+
+    #[test]
+    fn packet_header_follows_the_lan_protocol_layout() {
+        let broadcast = packet(0x1234_5678, 0, 7, LIGHT_GET, &[]);
+        assert_eq!(broadcast.len(), HEADER_LEN);
+        assert_eq!(broadcast[0..2], (HEADER_LEN as u16).to_le_bytes());
+        // Protocol 1024, addressable and tagged
+        assert_eq!(broadcast[2..4], 0x3400u16.to_le_bytes());
+        assert_eq!(broadcast[4..8], 0x1234_5678u32.to_le_bytes());
+        assert_eq!(broadcast[8..16], [0; 8]);
+        assert_eq!(broadcast[22], 0);
+        assert_eq!(broadcast[23], 7);
+        assert_eq!(broadcast[32..34], LIGHT_GET.to_le_bytes());
+
+        let targeted = packet(1, 0xd073_d5aa_bbcc, 8, LIGHT_SET_POWER, &[0xff, 0xff, 0, 0, 0, 0]);
+        assert_eq!(targeted.len(), HEADER_LEN + 6);
+        assert_eq!(targeted[0..2], ((HEADER_LEN + 6) as u16).to_le_bytes());
+        assert_eq!(targeted[2..4], 0x1400u16.to_le_bytes());
+        assert_eq!(targeted[8..16], 0xd073_d5aa_bbccu64.to_le_bytes());
+        assert_eq!(targeted[HEADER_LEN..], [0xff, 0xff, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn light_state_reads_into_a_bulb() {
+        let reply = packet(1, 0xd073_d5aa_bbcc, 0, LIGHT_STATE, &light_state());
+        let bulb = parse_state(&reply, addr()).unwrap();
+        assert_eq!(bulb.target, 0xd073_d5aa_bbcc);
+        assert_eq!(bulb.addr, addr());
+        assert_eq!(bulb.label, "Desk");
+        assert!(bulb.on);
+        assert_eq!((bulb.color.hue, bulb.color.saturation, bulb.color.brightness), (1000, 2000, 3000));
+        // The bulb sent no kelvin
+        assert_eq!(bulb.color.kelvin, DEFAULT_KELVIN);
+    }
+
+    #[test]
+    fn truncated_or_other_replies_are_not_a_light_state() {
+        let reply = packet(1, 0xd073_d5aa_bbcc, 0, LIGHT_STATE, &light_state());
+        // Cut short inside the label, and inside the header
+        assert!(parse_state(&reply[..HEADER_LEN + 20], addr()).is_none());
+        assert!(parse_state(&reply[..20], addr()).is_none());
+        // A full reply of another message type
+        let other = packet(1, 0xd073_d5aa_bbcc, 0, LIGHT_SET_COLOR, &light_state());
+        assert!(parse_state(&other, addr()).is_none());
+    }
+}
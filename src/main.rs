@@ -1,21 +1,88 @@
-use hueclient::{Bridge, CommandLight};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime};
+use hueclient::{Bridge, CommandLight, IdentifiedLight};
+use controller::{Builtin, ControllerError, LightController};
 use serde::{Deserialize, Serialize};
 use rust_socketio::{
-    client::ClientBuilder,
+    client::{Client, ClientBuilder, RawClient},
     payload::Payload,
-    Event,
 };
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::path::Path;
 use std::sync::Arc;
-use parking_lot::Mutex;
-use std::time::Duration;
+use parking_lot::{Mutex, RwLock};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 use std::fs;
 use hex::FromHex;
 use log::{info, warn, error, debug};
-use tokio::signal;
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 use thiserror::Error;
-use serde_json::Value;
+
+mod api;
+mod colors;
+mod config_file;
+mod controller;
+mod discord;
+mod duration;
+mod hue_remote;
+mod lifx;
+mod metrics;
+mod mqtt;
+mod wled;
+
+// Read when no `--config <path>` is given
+const CONFIG_PATH: &str = "config.json";
+const VALID_ALERTS: [&str; 3] = ["none", "select", "lselect"];
+// Highest brightness and saturation the bridge accepts
+const MAX_BRIGHTNESS: u8 = 254;
+const MAX_SATURATION: u8 = 254;
+// The bridge stops an "lselect" breathe after this long
+const LSELECT_CYCLE_MS: u64 = 15_000;
+// Values of the event `for` field that LumiaLive has mappings for
+const KNOWN_PLATFORMS: [&str; 3] = ["twitch_account", "streamlabs", "youtube_account"];
+// How long a changed config file must stay quiet before it's reloaded, since one save is often
+// several writes
+const CONFIG_RELOAD_DEBOUNCE_MS: u64 = 250;
+// YouTube paid message amounts arrive in millionths of the currency unit
+const YOUTUBE_MICROS_PER_UNIT: f64 = 1_000_000.0;
+// Recent event ids remembered to drop redelivered events, unless `dedup_buffer_size` is set
+const DEFAULT_DEDUP_BUFFER_SIZE: usize = 256;
+// Handled events kept in memory for the history export
+const EVENT_HISTORY_LEN: usize = 500;
+// Minimum gap between two bridge reconnect flashes
+const RECONNECT_FLASH_MIN_INTERVAL_MS: u64 = 60_000;
+// Delay between sweep steps when an effect doesn't set `sweep_step_ms`
+const DEFAULT_SWEEP_STEP_MS: u64 = 150;
+// Faster strobes than this overrun the bridge's command rate
+const MIN_STROBE_INTERVAL_MS: u64 = 100;
+// Longest a rainbow cycle may keep the handler busy, whatever its duration
+const MAX_RAINBOW_MS: u64 = 60_000;
+// White ambiance range Hue bulbs support, from cool (153 mirek, ~6500 K) to warm (500, ~2000 K)
+const COLOR_TEMP_MIREK_RANGE: std::ops::RangeInclusive<u16> = 153..=500;
+// Streamlabs reconnect backoff, doubling from the first delay up to the cap
+const SOCKET_RETRY_MIN_MS: u64 = 1_000;
+const SOCKET_RETRY_MAX_MS: u64 = 60_000;
+// Cap on the doubling backoff between light command retries
+const COMMAND_RETRY_MAX_MS: u64 = 30_000;
+// How long pairing waits for the bridge's link button, and how often it asks
+const PAIRING_TIMEOUT_MS: u64 = 60_000;
+const PAIRING_POLL_MS: u64 = 2_000;
+// Identifies LumiaLive in the bridge's list of paired apps
+const PAIRING_DEVICE_TYPE: &str = "lumialive#streaming";
+
+tokio::task_local! {
+    // The last effect played, tier picked and amount read while handling the current event
+    static HANDLED_EFFECT: RefCell<HandledEffect>;
+    // The event being handled, attached to the effects it queues
+    static EFFECT_SOURCE: EffectSource;
+}
 
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -24,308 +91,3928 @@ pub enum AppError {
     
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("TOML serialization error: {0}")]
+    TomlWrite(#[from] toml::ser::Error),
+
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
     
+    // Boxed because the socket error alone would make every Result<_, AppError> twice as large
     #[error("Socket.io error: {0}")]
-    SocketIo(#[from] rust_socketio::Error),
+    SocketIo(Box<rust_socketio::Error>),
+
+    #[error("File watch error: {0}")]
+    Watch(#[from] notify::Error),
     
     #[error("Bridge error: {0}")]
     Bridge(String),
     
     #[error("Invalid amount format: {0}")]
     InvalidAmount(String),
+
+    #[error("Config error: {0}")]
+    Config(String),
+}
+
+impl From<rust_socketio::Error> for AppError {
+    fn from(e: rust_socketio::Error) -> Self {
+        AppError::SocketIo(Box::new(e))
+    }
 }
 
 // Keep existing config structures
 #[derive(Debug, Deserialize, Serialize, Clone)]
 struct Config {
+    // File this config was loaded from, where API and pairing changes are written back
+    #[serde(skip)]
+    path: String,
     credentials: Credentials,
+    // JSON, TOML or YAML file whose credentials override the ones above
+    #[serde(default)]
+    secrets_file: Option<String>,
+    // JSON, TOML or YAML file of named effects that `effect_ref` entries resolve against
+    #[serde(default)]
+    effects_file: Option<String>,
     default_state: LightState,
     events: EventConfig,
+    #[serde(default)]
+    adaptive_tiers: AdaptiveTierConfig,
+    #[serde(default)]
+    api: ApiConfig,
+    // Publish every handled event to an MQTT broker, e.g. for Home Assistant automations
+    #[serde(default)]
+    mqtt: MqttConfig,
+    // Post a summary of handled events to a Discord channel
+    #[serde(default)]
+    discord: DiscordConfig,
+    // Prometheus scrape endpoint, separate from the HTTP API
+    #[serde(default)]
+    metrics: MetricsConfig,
+    #[serde(default)]
+    fallback_color: Option<String>,
+    // Color that effects with a `theme_blend` shift toward, e.g. for seasonal re-skins
+    #[serde(default)]
+    theme_color: Option<String>,
+    #[serde(default)]
+    time_profiles: Vec<TimeProfile>,
+    #[serde(default)]
+    unknown_platform_behavior: UnknownPlatformBehavior,
+    #[serde(default)]
+    unknown_platform_effect: Option<LightEffect>,
+    // Fixed seed for random light selection, for reproducible runs
+    #[serde(default)]
+    random_seed: Option<u64>,
+    // Which lights effects play on: the Hue bridge (default), LIFX bulbs or WLED strips
+    #[serde(default)]
+    backend: Backend,
+    #[serde(default)]
+    lifx: LifxConfig,
+    #[serde(default)]
+    wled: WledConfig,
+    #[serde(default)]
+    bridge_rediscovery: BridgeRediscoveryConfig,
+    #[serde(default)]
+    command_retry: CommandRetryConfig,
+    // Brief confirmation played once bridge commands work again after failing
+    #[serde(default)]
+    bridge_reconnect_effect: Option<LightEffect>,
+    // Named sets of light ids that effects can target with `light_group`
+    #[serde(default)]
+    light_groups: HashMap<String, Vec<usize>>,
+    // How often to re-check `light_groups` against the bridge's lights; omit to check only at startup
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    light_check_interval_ms: Option<u64>,
+    // Ramp lights that were off up from minimum brightness over this long
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    power_on_fade_ms: Option<u64>,
+    // At launch, fade lights from their current state to `default_state` over this long
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    startup_ramp_ms: Option<u64>,
+    // Log the commands effects would send instead of contacting the bridge
+    #[serde(default)]
+    dry_run: bool,
+    // Played once for the first handled event after startup
+    #[serde(default)]
+    first_event_effect: Option<LightEffect>,
+    #[serde(default)]
+    first_event_mode: FirstEventMode,
+    #[serde(default)]
+    flash_on_parse_error: ParseErrorFlashConfig,
+    #[serde(default)]
+    monthly_goal: MonthlyGoalConfig,
+    #[serde(default)]
+    session_goal: SessionGoalConfig,
+    #[serde(default)]
+    idle_watchdog: IdleWatchdogConfig,
+    // Cap on the summed brightness of all lights an effect sets
+    #[serde(default)]
+    max_total_brightness: Option<u32>,
+    // Per-light multiplier on effect brightness and saturation; unlisted lights use 1.0
+    #[serde(default)]
+    light_weights: HashMap<usize, f64>,
+    // Symmetric left/right light pairs, listed from the center outward
+    #[serde(default)]
+    mirror_pairs: Vec<(usize, usize)>,
+    // Gap kept after each effect's own duration before the next one may start
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    effect_cooldown_buffer_ms: Option<u64>,
+    // How many recent event ids to check redeliveries against; 0 turns deduplication off
+    #[serde(default)]
+    dedup_buffer_size: Option<usize>,
+    // Per-type cooldown for event mappings that don't set their own `cooldown_ms`
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    event_cooldown_ms: Option<u64>,
+    #[serde(default)]
+    scheduled_effects: Vec<ScheduledEffect>,
+    #[serde(default)]
+    hype_moments: Vec<HypeMoment>,
+    // After each handled event, drop queued events that a newer one of the same type supersedes
+    #[serde(default)]
+    drain_stale_on_resume: bool,
+    #[serde(default)]
+    queue_ordering: QueueOrdering,
+    // Effects queued or playing at once; past it, queued ones are dropped per `overflow_policy`
+    #[serde(default)]
+    max_active_effects: Option<usize>,
+    #[serde(default)]
+    overflow_policy: OverflowPolicy,
+    #[serde(default)]
+    rate_cap: RateCapConfig,
+    // Named event mappings that replace `events` while active, switched at runtime
+    #[serde(default)]
+    shows: HashMap<String, EventConfig>,
+    #[serde(default)]
+    active_show: Option<String>,
+    // Played when the active show is switched through the API
+    #[serde(default)]
+    show_transition_effect: Option<LightEffect>,
+    #[serde(default)]
+    zero_brightness: ZeroBrightness,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct Credentials {
-    streamlabs: StreamlabsCredentials,
-    hue: HueCredentials,
+// What an effect with `brightness: 0` does; Hue treats bri 0 on a lit light as minimum, not off
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ZeroBrightness {
+    // Turn the targeted lights off for the effect's duration
+    #[default]
+    Off,
+    // Refuse to load (or play) such effects
+    Reject,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct StreamlabsCredentials {
-    socket_token: String,
+// Order in which events that queued up behind a running effect are handled
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum QueueOrdering {
+    #[default]
+    Fifo,
+    // Highest event `priority` plus monetary value first; ties keep arrival order
+    Priority,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct HueCredentials {
-    username: String,
-    bridge_ip: Option<String>,
+// Which queued effect is dropped when `max_active_effects` is reached; the playing one never is
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum OverflowPolicy {
+    #[default]
+    #[serde(rename = "drop_oldest")]
+    Oldest,
+    #[serde(rename = "drop_newest")]
+    Newest,
+    // Lowest effect `priority`, then lowest event priority plus value; ties drop the newest
+    #[serde(rename = "drop_lowest_priority")]
+    LowestPriority,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct LightState {
-    on: bool,
-    brightness: u8,
-    hue: u16,
-    saturation: u8,
-    alert: String,
+impl OverflowPolicy {
+    fn reason(self) -> &'static str {
+        match self {
+            OverflowPolicy::Oldest => "the oldest",
+            OverflowPolicy::Newest => "the newest",
+            OverflowPolicy::LowestPriority => "the lowest-priority",
+        }
+    }
 }
 
+// Caps handled events per minute; events over the cap are pooled and one is sampled per free slot
 #[derive(Debug, Deserialize, Serialize, Clone)]
-struct EventConfig {
-    donation: EventTieredEffect,
-    twitch_follow: SimpleEventEffect,
-    twitch_subscription: SimpleEventEffect,
-    twitch_bits: EventTieredEffect,
+#[serde(default)]
+struct RateCapConfig {
+    enabled: bool,
+    max_per_minute: usize,
+    sampling: SamplingPolicy,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct SimpleEventEffect {
-    enabled: bool,
-    effect: LightEffect,
+impl Default for RateCapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_per_minute: 30,
+            sampling: SamplingPolicy::HighestValue,
+        }
+    }
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SamplingPolicy {
+    Random,
+    // Keep the event worth the most, with bits and YouTube amounts converted to currency units
+    #[default]
+    HighestValue,
+}
+
+// An effect played once at a local wall-clock time, e.g. "2026-10-14T20:00:00"
 #[derive(Debug, Deserialize, Serialize, Clone)]
-struct EventTieredEffect {
-    enabled: bool,
-    tiers: Vec<TierEffect>,
+struct ScheduledEffect {
+    time: NaiveDateTime,
+    effect: LightEffect,
 }
 
+// A combined effect played when every one of `event_types` has played an effect within `window_ms`
 #[derive(Debug, Deserialize, Serialize, Clone)]
-struct TierEffect {
-    amount: f64,
+struct HypeMoment {
+    name: String,
+    event_types: Vec<String>,
+    #[serde(deserialize_with = "duration::deserialize")]
+    window_ms: u64,
     effect: LightEffect,
 }
 
+// Safety net: once effects have been quiet for `idle_ms`, put lights left in an effect back to default
 #[derive(Debug, Deserialize, Serialize, Clone)]
-struct LightEffect {
-    color: String,
-    brightness: u8,
-    alert: String,
-    duration: u64,
+#[serde(default)]
+struct IdleWatchdogConfig {
+    enabled: bool,
+    #[serde(deserialize_with = "duration::deserialize")]
+    idle_ms: u64,
 }
 
-// Updated Streamlabs event structures
-#[derive(Debug, Deserialize)]
-struct StreamlabsEvent {
-    event_id: String,
-    #[serde(rename = "for")]
-    event_for: Option<String>,
-    #[serde(rename = "type")]
-    event_type: String,
-    message: Vec<EventMessage>,
+impl Default for IdleWatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_ms: 300_000,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct EventMessage {
-    #[serde(default)]
-    name: String,
-    #[serde(default)]
-    amount: Option<String>,
-    #[serde(default)]
-    formatted_amount: Option<String>,
-    #[serde(default)]
-    _id: String,
-    #[serde(default)]
-    id: Option<String>,
-    payload: Option<EventPayload>,
+// Milestones on cumulative donations for the calendar month, persisted across restarts
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct MonthlyGoalConfig {
+    enabled: bool,
+    state_file: String,
+    milestones: Vec<TierEffect>,
 }
 
-#[derive(Debug, Deserialize)]
-struct EventPayload {
-    #[serde(default)]
-    name: String,
-    #[serde(default)]
-    id: Option<String>,
-    #[serde(default)]
-    priority: Option<i32>,
+impl Default for MonthlyGoalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            state_file: "monthly_goal.json".to_string(),
+            milestones: Vec::new(),
+        }
+    }
 }
 
-struct AppState {
-    bridge: Arc<Mutex<Bridge>>,
-    config: Config,
+// Milestones on donations plus bits (converted to dollars) received this session
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct SessionGoalConfig {
+    enabled: bool,
+    // Dollar value of a single bit
+    bits_to_dollars: f64,
+    milestones: Vec<TierEffect>,
 }
 
-// Existing hex_to_hue function remains the same
-fn hex_to_hue(hex: &str) -> Result<(u16, u8), AppError> {
-    let hex = hex.trim_start_matches('#');
-    let rgb = Vec::from_hex(hex)
-        .map_err(|e| AppError::Bridge(format!("Invalid hex color: {}", e)))?;
-    
-    if rgb.len() != 3 {
-        return Err(AppError::Bridge("Invalid RGB values".to_string()));
+impl Default for SessionGoalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bits_to_dollars: 0.01,
+            milestones: Vec::new(),
+        }
     }
-    
-    let (r, g, b) = (rgb[0] as f32 / 255.0, rgb[1] as f32 / 255.0, rgb[2] as f32 / 255.0);
-    
-    let max = r.max(g).max(b);
-    let min = r.min(g).min(b);
-    let delta = max - min;
-    
-    let hue = if delta == 0.0 {
-        0.0
-    } else if max == r {
-        60.0 * (((g - b) / delta) % 6.0)
-    } else if max == g {
-        60.0 * ((b - r) / delta + 2.0)
-    } else {
-        60.0 * ((r - g) / delta + 4.0)
-    };
-    
-    let saturation = if max == 0.0 { 0.0 } else { delta / max };
-    
-    let hue = ((hue / 360.0) * 65535.0) as u16;
-    let saturation = (saturation * 254.0) as u8;
-    
-    Ok((hue, saturation))
 }
 
-impl AppState {
-    async fn handle_event(&self, event: StreamlabsEvent) -> Result<(), AppError> {
-        info!("Processing event: {:?}", event);
-        
-        let result = match (event.event_type.as_str(), event.event_for.as_deref()) {
-            ("donation", None) if self.config.events.donation.enabled => {
-                info!("Handling donation event");
-                if let Some(message) = event.message.first() {
-                    self.handle_donation(message).await?;
-                }
-                Ok(())
-            },
-            ("follow", Some("twitch_account")) if self.config.events.twitch_follow.enabled => {
-                info!("Handling Twitch follow event");
-                self.handle_twitch_follow().await
-            },
-            ("subscription", Some("twitch_account")) if self.config.events.twitch_subscription.enabled => {
-                info!("Handling Twitch subscription event");
-                self.handle_twitch_subscription().await
-            },
-            ("bits", Some("twitch_account")) if self.config.events.twitch_bits.enabled => {
-                info!("Handling Twitch bits event");
-                if let Some(message) = event.message.first() {
-                    self.handle_bits(message).await?;
-                }
-                Ok(())
-            },
-            _ => {
-                info!("Unhandled or disabled event: type={}, for={:?}", 
-                      event.event_type, event.event_for);
-                Ok(())
-            }
-        };
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+struct MonthlyTotal {
+    // Local calendar month as "YYYY-MM"
+    month: String,
+    total: f64,
+}
 
-        if let Err(e) = &result {
-            error!("Error processing event: {}", e);
+impl MonthlyTotal {
+    fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable monthly goal state in {}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
         }
-        
-        result
     }
 
-    async fn handle_donation(&self, message: &EventMessage) -> Result<(), AppError> {
-        if let Some(amount_str) = &message.amount {
-            let amount: f64 = amount_str.parse()
-                .map_err(|_| AppError::InvalidAmount(amount_str.clone()))?;
-            
-            let effect = self.config.events.donation.tiers
-                .iter()
-                .find(|tier| amount >= tier.amount)
-                .map(|tier| &tier.effect)
-                .unwrap_or_else(|| &self.config.events.donation.tiers.last().unwrap().effect);
-            
-            info!("Processing donation of {} from {}", amount_str, message.name);
-            self.apply_effect(effect).await?;
-        }
-        
+    fn save(&self, path: &str) -> Result<(), AppError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
         Ok(())
     }
+}
 
-    async fn handle_twitch_follow(&self) -> Result<(), AppError> {
-        info!("Processing Twitch follow");
-        self.apply_effect(&self.config.events.twitch_follow.effect).await
-    }
+// Setup aid: flash the lights when a Streamlabs message fails to parse
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct ParseErrorFlashConfig {
+    enabled: bool,
+    effect: LightEffect,
+    #[serde(deserialize_with = "duration::deserialize")]
+    min_interval_ms: u64,
+}
 
-    async fn handle_twitch_subscription(&self) -> Result<(), AppError> {
-        info!("Processing Twitch subscription");
-        self.apply_effect(&self.config.events.twitch_subscription.effect).await
+impl Default for ParseErrorFlashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            effect: LightEffect {
+                color: "#ffcc00".to_string(),
+                brightness: 60,
+                alert: "select".to_string(),
+                duration: 1000,
+                ..Default::default()
+            },
+            min_interval_ms: 30_000,
+        }
     }
+}
 
-    async fn handle_bits(&self, message: &EventMessage) -> Result<(), AppError> {
-        if let Some(amount_str) = &message.amount {
-            let amount: f64 = amount_str.parse()
-                .map_err(|_| AppError::InvalidAmount(amount_str.clone()))?;
-            
-            let effect = self.config.events.twitch_bits.tiers
-                .iter()
-                .find(|tier| amount >= tier.amount)
-                .map(|tier| &tier.effect)
-                .unwrap_or_else(|| &self.config.events.twitch_bits.tiers.last().unwrap().effect);
-            
-            info!("Processing {} bits from {}", amount_str, message.name);
-            self.apply_effect(effect).await?;
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum FirstEventMode {
+    // Play the first-event effect, then the event's own effect
+    #[default]
+    InAddition,
+    // Play only the first-event effect
+    Instead,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Backend {
+    #[default]
+    Hue,
+    Lifx,
+    Wled,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct LifxConfig {
+    // Where discovery requests are sent; a bulb's own address works too
+    broadcast: String,
+    // How long to wait for bulbs to answer
+    #[serde(deserialize_with = "duration::deserialize")]
+    timeout_ms: u64,
+}
+
+impl Default for LifxConfig {
+    fn default() -> Self {
+        Self {
+            broadcast: "255.255.255.255:56700".to_string(),
+            timeout_ms: 500,
         }
-        
-        Ok(())
     }
+}
 
-    async fn apply_effect(&self, effect: &LightEffect) -> Result<(), AppError> {
-        info!("Applying light effect: {:?}", effect);
-        let bridge = self.bridge.lock();
-        let (hue, sat) = hex_to_hue(&effect.color)?;
-        
-        let mut command = CommandLight::default();
-        command.on = Some(true);
-        command.bri = Some(effect.brightness);
-        command.hue = Some(hue);
-        command.sat = Some(sat);
-        command.alert = Some(effect.alert.clone());
-
-        info!("Created light command with hue={}, sat={}", hue, sat);
-        
-        let lights = bridge.get_all_lights()
-            .map_err(|e| AppError::Bridge(e.to_string()))?;
-            
-        info!("Applying effect to {} lights", lights.len());
-        for light in lights {
-            info!("Setting state for light {}", light.id);
-            bridge.set_light_state(light.id, &command)
-                .map_err(|e| AppError::Bridge(e.to_string()))?;
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct WledConfig {
+    // IP addresses or host names, one light each in this order
+    devices: Vec<String>,
+    #[serde(deserialize_with = "duration::deserialize")]
+    timeout_ms: u64,
+    // WLED effects strobes and rainbows play as; null sends them command by command instead
+    strobe_effect: Option<String>,
+    rainbow_effect: Option<String>,
+}
+
+impl Default for WledConfig {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            timeout_ms: 2000,
+            strobe_effect: Some("Strobe".to_string()),
+            rainbow_effect: Some("Rainbow".to_string()),
         }
+    }
+}
 
-        info!("Waiting {} ms before resetting", effect.duration);
-        sleep(Duration::from_millis(effect.duration)).await;
-        
-        info!("Resetting lights to default state");
-        let mut reset_command = CommandLight::default();
-        reset_command.on = Some(self.config.default_state.on);
-        reset_command.bri = Some(self.config.default_state.brightness);
-        reset_command.hue = Some(self.config.default_state.hue);
-        reset_command.sat = Some(self.config.default_state.saturation);
-        reset_command.alert = Some(self.config.default_state.alert.clone());
+// Re-runs bridge discovery after repeated command failures, e.g. when DHCP hands the bridge a new IP
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct BridgeRediscoveryConfig {
+    enabled: bool,
+    failure_threshold: u32,
+    // Discovery attempts before giving up on the failed effect
+    max_attempts: u32,
+    // Wait before the second attempt, doubling for each one after up to `max_delay_ms`
+    #[serde(deserialize_with = "duration::deserialize")]
+    retry_delay_ms: u64,
+    #[serde(deserialize_with = "duration::deserialize")]
+    max_delay_ms: u64,
+}
 
-        let lights = bridge.get_all_lights()
-            .map_err(|e| AppError::Bridge(e.to_string()))?;
-            
-        for light in lights {
-            info!("Resetting light {}", light.id);
-            bridge.set_light_state(light.id, &reset_command)
-                .map_err(|e| AppError::Bridge(e.to_string()))?;
+impl Default for BridgeRediscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failure_threshold: 3,
+            max_attempts: 3,
+            retry_delay_ms: 2_000,
+            max_delay_ms: 60_000,
         }
-        
-        Ok(())
     }
 }
 
-fn process_event(message: &str, tx: &mpsc::Sender<StreamlabsEvent>) {
-    info!("Processing message: {}", message);
-    match serde_json::from_str::<StreamlabsEvent>(message) {
-        Ok(event) => {
-            info!("Successfully parsed event: {:?}", event);
-            match (event.event_type.as_str(), event.event_for.as_deref()) {
-                ("donation", None) |
-                ("follow", Some("twitch_account")) |
-                ("subscription", Some("twitch_account")) |
-                ("bits", Some("twitch_account")) => {
-                    info!("Sending valid event to handler: {:?}", event);
-                    if let Err(e) = tx.blocking_send(event) {
-                        error!("Failed to send event to handler: {}", e);
+impl BridgeRediscoveryConfig {
+    /// The wait before discovery attempt `attempt`, counted from 0; the first has none.
+    fn retry_delay(&self, attempt: u32) -> u64 {
+        let Some(doublings) = attempt.checked_sub(1) else {
+            return 0;
+        };
+        self.retry_delay_ms
+            .saturating_mul(1u64.checked_shl(doublings).unwrap_or(u64::MAX))
+            .min(self.max_delay_ms)
+    }
+}
+
+// Separate retry policies: live effect commands fail fast to stay timely, resets try harder
+// because a lost reset leaves lights stuck in the effect
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct CommandRetryConfig {
+    effect: RetryPolicy,
+    reset: RetryPolicy,
+}
+
+impl Default for CommandRetryConfig {
+    fn default() -> Self {
+        Self {
+            effect: RetryPolicy {
+                enabled: true,
+                max_retries: 1,
+                backoff_ms: 100,
+                retry_all_errors: false,
+            },
+            reset: RetryPolicy {
+                enabled: true,
+                max_retries: 5,
+                backoff_ms: 250,
+                retry_all_errors: true,
+            },
+        }
+    }
+}
+
+// Retries a failed light command, doubling the delay each attempt
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct RetryPolicy {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    max_retries: u32,
+    #[serde(default, deserialize_with = "duration::deserialize")]
+    backoff_ms: u64,
+    // Retry on any failure, not only when the bridge reports it is busy
+    #[serde(default)]
+    retry_all_errors: bool,
+}
+
+// Which retry policy a light command falls under
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommandKind {
+    Effect,
+    Reset,
+}
+
+/// Whether `name` is the bridge group's name or id.
+fn group_matches(group: &hueclient::IdentifiedGroup, name: &str) -> bool {
+    group.group.name == name || group.id.to_string() == name
+}
+
+fn check_command_response(response: serde_json::Value, target: &str, id: usize) -> Result<(), ControllerError> {
+    match response_error(&response) {
+        Some(e) if is_rate_limited(&e) => Err(e.into()),
+        Some(e) => {
+            debug!("Bridge rejected part of the command for {} {}: {}", target, id, e);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Whether the bridge turned a command away because it is overloaded rather than invalid.
+fn is_rate_limited(e: &hueclient::HueError) -> bool {
+    if let hueclient::HueError::BridgeError { code: 901, msg } = e {
+        // "Internal error, 503" is how the bridge reports a full command buffer
+        if msg.contains("503") {
+            return true;
+        }
+    }
+    let msg = e.to_string().to_lowercase();
+    ["429", "too many", "rate limit", "busy"].iter().any(|needle| msg.contains(needle))
+}
+
+/// The first error entry in a light state response, which hueclient reports as success.
+fn response_error(response: &serde_json::Value) -> Option<hueclient::HueError> {
+    response.as_array()?.iter().find_map(|entry| {
+        let error = entry.get("error")?;
+        Some(hueclient::HueError::BridgeError {
+            code: error.get("type").and_then(|t| t.as_u64()).unwrap_or(0) as usize,
+            msg: error.get("description").and_then(|d| d.as_str()).unwrap_or_default().to_string(),
+        })
+    })
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum UnknownPlatformBehavior {
+    #[default]
+    Ignore,
+    Warn,
+    DefaultEffect,
+}
+
+fn is_unknown_platform(event_for: Option<&str>) -> bool {
+    event_for.is_some_and(|platform| !KNOWN_PLATFORMS.contains(&platform))
+}
+
+// Event mappings used instead of `events` between `start` and `end` local time
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct TimeProfile {
+    name: String,
+    start: NaiveTime,
+    end: NaiveTime,
+    events: EventConfig,
+}
+
+impl TimeProfile {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            // Range wraps past midnight, e.g. 22:00-06:00
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Which of the config's event mappings are in use.
+#[derive(Debug, Clone, PartialEq)]
+enum EventSource {
+    Events,
+    // Index into `time_profiles`
+    TimeProfile(usize),
+    Show(String),
+}
+
+impl Config {
+    /// Event mappings for the current local time, falling back to the active show, then `events`.
+    fn active_events(&self) -> &EventConfig {
+        match self.active_event_source() {
+            EventSource::TimeProfile(index) => {
+                debug!("Using time profile {}", self.time_profiles[index].name);
+                &self.time_profiles[index].events
+            }
+            EventSource::Show(show) => &self.shows[&show],
+            EventSource::Events => &self.events,
+        }
+    }
+
+    /// Where `active_events` comes from right now.
+    fn active_event_source(&self) -> EventSource {
+        let now = Local::now().time();
+        if let Some(index) = self.time_profiles.iter().position(|profile| profile.contains(now)) {
+            return EventSource::TimeProfile(index);
+        }
+        match self.active_show.as_ref().filter(|show| self.shows.contains_key(*show)) {
+            Some(show) => EventSource::Show(show.clone()),
+            None => EventSource::Events,
+        }
+    }
+
+    /// The event mappings `source` names, which must have come from this config.
+    fn events_mut(&mut self, source: &EventSource) -> &mut EventConfig {
+        match source {
+            EventSource::Events => &mut self.events,
+            EventSource::TimeProfile(index) => &mut self.time_profiles[*index].events,
+            EventSource::Show(show) => self.shows.get_mut(show).expect("active show exists"),
+        }
+    }
+
+    /// Every effect anywhere in the config: event mappings, profiles, shows and standalone effects.
+    fn all_effects(&self) -> Vec<&LightEffect> {
+        let profiles = self.time_profiles.iter().map(|profile| &profile.events);
+        let mut effects: Vec<&LightEffect> = std::iter::once(&self.events)
+            .chain(profiles)
+            .chain(self.shows.values())
+            .flat_map(|events| events.effects())
+            .collect();
+        effects.extend(self.scheduled_effects.iter().map(|scheduled| &scheduled.effect));
+        effects.extend(self.monthly_goal.milestones.iter().map(|milestone| &milestone.effect));
+        effects.extend(self.session_goal.milestones.iter().map(|milestone| &milestone.effect));
+        effects.extend(&self.first_event_effect);
+        effects.extend(&self.unknown_platform_effect);
+        effects.extend(&self.bridge_reconnect_effect);
+        effects.extend(&self.show_transition_effect);
+        effects.push(&self.flash_on_parse_error.effect);
+        effects
+    }
+
+    /// Checks brightness, saturation and alert values against what the bridge accepts,
+    /// failing with every out-of-range value found rather than just the first.
+    fn validate(&self) -> Result<(), AppError> {
+        let mut problems = Vec::new();
+        let state = &self.default_state;
+        if state.brightness > MAX_BRIGHTNESS {
+            problems.push(format!("default_state brightness {} is outside 0-{}", state.brightness, MAX_BRIGHTNESS));
+        }
+        if state.saturation > MAX_SATURATION {
+            problems.push(format!("default_state saturation {} is outside 0-{}", state.saturation, MAX_SATURATION));
+        }
+        if !VALID_ALERTS.contains(&state.alert.as_str()) {
+            problems.push(format!("default_state alert {:?} is not one of {:?}", state.alert, VALID_ALERTS));
+        }
+        let profiles = self.time_profiles.iter().map(|profile| &profile.events);
+        for events in std::iter::once(&self.events).chain(profiles).chain(self.shows.values()) {
+            let bits = &events.twitch_bits;
+            match &bits.continuous {
+                Some(mapping) if bits.tiers.is_empty() && mapping.color.is_none() => {
+                    problems.push("twitch_bits continuous has no tiers, so it needs its own color".to_string());
+                }
+                Some(ContinuousMapping { alert: Some(alert), .. }) if !VALID_ALERTS.contains(&alert.as_str()) => {
+                    problems.push(format!("twitch_bits continuous alert {:?} is not one of {:?}", alert, VALID_ALERTS));
+                }
+                _ => {}
+            }
+        }
+        if self.max_active_effects == Some(0) {
+            problems.push("max_active_effects is 0, which would drop every effect".to_string());
+        }
+
+        for effect in self.all_effects() {
+            if effect.brightness > MAX_BRIGHTNESS {
+                problems.push(format!(
+                    "Effect with color {} has brightness {}, outside 0-{}",
+                    effect.color, effect.brightness, MAX_BRIGHTNESS
+                ));
+            }
+            if !VALID_ALERTS.contains(&effect.alert.as_str()) {
+                problems.push(format!(
+                    "Effect with color {} has alert {:?}, expected one of {:?}",
+                    effect.color, effect.alert, VALID_ALERTS
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(AppError::Config(format!(
+            "{} invalid value(s) in {}:\n  {}",
+            problems.len(),
+            self.path,
+            problems.join("\n  ")
+        )))
+    }
+
+    /// Fails on any `brightness: 0` effect when `zero_brightness` is `reject`.
+    fn check_zero_brightness(&self) -> Result<(), AppError> {
+        if self.zero_brightness != ZeroBrightness::Reject {
+            return Ok(());
+        }
+        match self.all_effects().iter().find(|effect| effect.brightness == 0) {
+            Some(effect) => Err(AppError::Config(format!(
+                "Effect with color {} has brightness 0, which zero_brightness \"reject\" does not allow",
+                effect.color
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes `show` the active show, or returns to `events` with `None`.
+    fn select_show(&mut self, show: Option<&str>) -> Result<(), AppError> {
+        if let Some(name) = show {
+            if !self.shows.contains_key(name) {
+                return Err(AppError::Config(format!("Unknown show {:?}", name)));
+            }
+        }
+        self.active_show = show.map(str::to_string);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct ApiConfig {
+    enabled: bool,
+    bind: String,
+    port: u16,
+    // Serve the effect preview page at /
+    preview_ui: bool,
+    // Serve a status page with recent events and the lights at /dashboard
+    dashboard: bool,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "127.0.0.1".to_string(),
+            port: 8080,
+            preview_ui: true,
+            dashboard: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct MqttConfig {
+    enabled: bool,
+    host: String,
+    port: u16,
+    client_id: String,
+    topic: String,
+    // Keep the last message on the broker for clients that subscribe later
+    retain: bool,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "lumialive".to_string(),
+            topic: "lumialive/events".to_string(),
+            retain: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct MetricsConfig {
+    enabled: bool,
+    bind: String,
+    port: u16,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "127.0.0.1".to_string(),
+            port: 9898,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+struct DiscordConfig {
+    enabled: bool,
+    webhook_url: String,
+    // Streamlabs event types to post, e.g. "donation" or "follow"; empty posts every type
+    event_types: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct Credentials {
+    streamlabs: StreamlabsCredentials,
+    hue: HueCredentials,
+    // Only needed when the broker requires a login
+    #[serde(default)]
+    mqtt: Option<MqttCredentials>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct MqttCredentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct StreamlabsCredentials {
+    #[serde(default)]
+    socket_token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct HueCredentials {
+    #[serde(default)]
+    username: String,
+    bridge_ip: Option<String>,
+    // Reach the bridge through the Hue Remote API instead of the LAN
+    #[serde(default)]
+    remote: HueRemoteConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct HueRemoteConfig {
+    enabled: bool,
+    // OAuth client of a Hue developer app
+    client_id: String,
+    client_secret: String,
+    // Only read on the first start; later refresh tokens are kept in `token_file`
+    refresh_token: String,
+    token_file: String,
+}
+
+impl Default for HueRemoteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            client_id: String::new(),
+            client_secret: String::new(),
+            refresh_token: String::new(),
+            token_file: "hue_remote_tokens.json".to_string(),
+        }
+    }
+}
+
+// Shape of `secrets_file`; every field is optional so only the secrets present override
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Secrets {
+    #[serde(default)]
+    streamlabs: Option<StreamlabsSecrets>,
+    #[serde(default)]
+    hue: Option<HueSecrets>,
+    #[serde(default)]
+    mqtt: Option<MqttCredentials>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StreamlabsSecrets {
+    socket_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HueSecrets {
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    remote: Option<HueRemoteSecrets>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HueRemoteSecrets {
+    client_id: String,
+    client_secret: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+impl Credentials {
+    fn merge_secrets(&mut self, secrets: Secrets) {
+        if let Some(streamlabs) = secrets.streamlabs {
+            self.streamlabs.socket_token = streamlabs.socket_token;
+        }
+        if let Some(hue) = secrets.hue {
+            if let Some(username) = hue.username {
+                self.hue.username = username;
+            }
+            if let Some(remote) = hue.remote {
+                self.hue.remote.client_id = remote.client_id;
+                self.hue.remote.client_secret = remote.client_secret;
+                if let Some(refresh_token) = remote.refresh_token {
+                    self.hue.remote.refresh_token = refresh_token;
+                }
+            }
+        }
+        if let Some(mqtt) = secrets.mqtt {
+            self.mqtt = Some(mqtt);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct LightState {
+    on: bool,
+    brightness: u8,
+    hue: u16,
+    saturation: u8,
+    alert: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct EventConfig {
+    donation: DonationEffect,
+    twitch_follow: SimpleEventEffect,
+    // Tiers keyed on cumulative months (1, 3, 6, 12...); the highest reached wins
+    #[serde(deserialize_with = "deserialize_subscription")]
+    twitch_subscription: EventTieredEffect,
+    // Gifted subs, including community gifts, tiered on the number of subs gifted at once;
+    // they play a month tier instead when this isn't enabled
+    #[serde(default)]
+    twitch_gift_subscription: EventTieredEffect,
+    twitch_bits: EventTieredEffect,
+    #[serde(default)]
+    streamlabs_loyalty: RewardEffects,
+    #[serde(default)]
+    twitch_redemptions: RedemptionEffects,
+    // Tiers keyed on the sticker's value in its own currency
+    #[serde(default)]
+    youtube_super_sticker: EventTieredEffect,
+    // Tiers keyed on the Super Chat's value in its own currency
+    #[serde(default)]
+    youtube_superchat: EventTieredEffect,
+    #[serde(default)]
+    youtube_membership: SimpleEventEffect,
+    // Tiers keyed on the number of raiders
+    #[serde(default)]
+    twitch_raid: EventTieredEffect,
+    #[serde(default)]
+    twitch_host: SimpleEventEffect,
+}
+
+impl EventConfig {
+    fn enabled_mut(&mut self, event_type: &str) -> Option<&mut bool> {
+        match event_type {
+            "donation" => Some(&mut self.donation.enabled),
+            "twitch_follow" => Some(&mut self.twitch_follow.enabled),
+            "twitch_subscription" => Some(&mut self.twitch_subscription.enabled),
+            "twitch_gift_subscription" => Some(&mut self.twitch_gift_subscription.enabled),
+            "twitch_bits" => Some(&mut self.twitch_bits.enabled),
+            "streamlabs_loyalty" => Some(&mut self.streamlabs_loyalty.enabled),
+            "twitch_redemptions" => Some(&mut self.twitch_redemptions.enabled),
+            "youtube_super_sticker" => Some(&mut self.youtube_super_sticker.enabled),
+            "youtube_superchat" => Some(&mut self.youtube_superchat.enabled),
+            "youtube_membership" => Some(&mut self.youtube_membership.enabled),
+            "twitch_raid" => Some(&mut self.twitch_raid.enabled),
+            "twitch_host" => Some(&mut self.twitch_host.enabled),
+            _ => None,
+        }
+    }
+
+    /// Cooldown of the mapping an incoming event type falls under, if it sets one. Events of
+    /// that type are ignored for this long after one plays an effect, overriding the global
+    /// `event_cooldown_ms`.
+    fn cooldown_ms(&self, event_type: &str) -> Option<u64> {
+        match event_type {
+            "donation" => self.donation.cooldown_ms,
+            "follow" => self.twitch_follow.cooldown_ms,
+            "subscription" | "resub" => self.twitch_subscription.cooldown_ms,
+            "subMysteryGift" => self.twitch_gift_subscription.cooldown_ms,
+            "bits" => self.twitch_bits.cooldown_ms,
+            "loyalty_store_redemption" => self.streamlabs_loyalty.cooldown_ms,
+            "redemption" => self.twitch_redemptions.cooldown_ms,
+            "supersticker" => self.youtube_super_sticker.cooldown_ms,
+            "superchat" => self.youtube_superchat.cooldown_ms,
+            "membership" => self.youtube_membership.cooldown_ms,
+            "raid" => self.twitch_raid.cooldown_ms,
+            "host" => self.twitch_host.cooldown_ms,
+            _ => None,
+        }
+    }
+
+    /// Configured base priority of the mapping an incoming event type falls under.
+    fn priority(&self, event_type: &str) -> i32 {
+        match event_type {
+            "donation" => self.donation.priority,
+            "follow" => self.twitch_follow.priority,
+            "subscription" | "resub" => self.twitch_subscription.priority.max(self.twitch_gift_subscription.priority),
+            "subMysteryGift" => self.twitch_gift_subscription.priority,
+            "bits" => self.twitch_bits.priority,
+            "loyalty_store_redemption" => self.streamlabs_loyalty.priority,
+            "redemption" => self.twitch_redemptions.priority,
+            "supersticker" => self.youtube_super_sticker.priority,
+            "superchat" => self.youtube_superchat.priority,
+            "membership" => self.youtube_membership.priority,
+            "raid" => self.twitch_raid.priority,
+            "host" => self.twitch_host.priority,
+            _ => 0,
+        }
+    }
+
+    /// Enabled flag per event type, keyed by config name.
+    fn enabled_states(&self) -> BTreeMap<&'static str, bool> {
+        BTreeMap::from([
+            ("donation", self.donation.enabled),
+            ("twitch_follow", self.twitch_follow.enabled),
+            ("twitch_subscription", self.twitch_subscription.enabled),
+            ("twitch_gift_subscription", self.twitch_gift_subscription.enabled),
+            ("twitch_bits", self.twitch_bits.enabled),
+            ("streamlabs_loyalty", self.streamlabs_loyalty.enabled),
+            ("twitch_redemptions", self.twitch_redemptions.enabled),
+            ("youtube_super_sticker", self.youtube_super_sticker.enabled),
+            ("youtube_superchat", self.youtube_superchat.enabled),
+            ("youtube_membership", self.youtube_membership.enabled),
+            ("twitch_raid", self.twitch_raid.enabled),
+            ("twitch_host", self.twitch_host.enabled),
+        ])
+    }
+
+    /// Every effect in these mappings, including each tier.
+    fn effects(&self) -> Vec<&LightEffect> {
+        let mut effects = vec![&self.twitch_follow.effect];
+        // Unconfigured optional mappings hold a blank, disabled effect
+        for optional in [&self.youtube_membership, &self.twitch_host] {
+            effects.extend(Some(&optional.effect).filter(|_| optional.enabled));
+        }
+        effects.extend(self.twitch_subscription.tiers.iter().map(|tier| &tier.effect));
+        effects.extend(self.twitch_gift_subscription.tiers.iter().map(|tier| &tier.effect));
+        effects.extend(self.donation.tiers.iter().map(|tier| &tier.effect));
+        effects.extend(self.twitch_bits.tiers.iter().map(|tier| &tier.effect));
+        effects.extend(self.streamlabs_loyalty.rewards.values());
+        effects.extend(self.twitch_redemptions.rewards.iter().map(|redemption| &redemption.effect));
+        effects.extend(self.youtube_super_sticker.tiers.iter().map(|tier| &tier.effect));
+        effects.extend(self.youtube_superchat.tiers.iter().map(|tier| &tier.effect));
+        effects.extend(self.twitch_raid.tiers.iter().map(|tier| &tier.effect));
+        effects
+    }
+
+    fn tiers(&self, event_type: &str) -> Option<&[TierEffect]> {
+        match event_type {
+            "donation" => Some(&self.donation.tiers),
+            "twitch_subscription" => Some(&self.twitch_subscription.tiers),
+            "twitch_gift_subscription" => Some(&self.twitch_gift_subscription.tiers),
+            "twitch_bits" => Some(&self.twitch_bits.tiers),
+            "youtube_super_sticker" => Some(&self.youtube_super_sticker.tiers),
+            "youtube_superchat" => Some(&self.youtube_superchat.tiers),
+            "twitch_raid" => Some(&self.twitch_raid.tiers),
+            _ => None,
+        }
+    }
+
+    /// Looks up the effect for an event type by its config key. Tiered events
+    /// need the index of the tier to edit.
+    fn effect_mut(&mut self, event_type: &str, tier: Option<usize>) -> Result<&mut LightEffect, String> {
+        let tiers = match event_type {
+            "twitch_follow" => return Ok(&mut self.twitch_follow.effect),
+            "youtube_membership" => return Ok(&mut self.youtube_membership.effect),
+            "twitch_host" => return Ok(&mut self.twitch_host.effect),
+            "donation" => &mut self.donation.tiers,
+            "twitch_subscription" => &mut self.twitch_subscription.tiers,
+            "twitch_gift_subscription" => &mut self.twitch_gift_subscription.tiers,
+            "twitch_bits" => &mut self.twitch_bits.tiers,
+            "youtube_super_sticker" => &mut self.youtube_super_sticker.tiers,
+            "youtube_superchat" => &mut self.youtube_superchat.tiers,
+            "twitch_raid" => &mut self.twitch_raid.tiers,
+            _ => return Err(format!("Unknown event type: {}", event_type)),
+        };
+
+        let index = tier.ok_or_else(|| format!("Event type {} is tiered; a tier index is required", event_type))?;
+        let count = tiers.len();
+        tiers.get_mut(index)
+            .map(|tier| &mut tier.effect)
+            .ok_or_else(|| format!("Tier {} out of range for {} ({} tiers)", index, event_type, count))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+struct SimpleEventEffect {
+    enabled: bool,
+    effect: LightEffect,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    cooldown_ms: Option<u64>,
+}
+
+/// Reads `twitch_subscription`, pointing configs still in the old flat shape at the tiered one.
+fn deserialize_subscription<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<EventTieredEffect, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let old_shape = ["effect", "resub_effect", "resub_tiers"].iter().any(|key| value.get(key).is_some());
+    if old_shape && value.get("tiers").is_none() {
+        return Err(serde::de::Error::custom(
+            "twitch_subscription is now tiered on cumulative months: move `effect` into `tiers` as \
+             { \"amount\": 1, \"effect\": ... }, add each `resub_tiers` entry (and `resub_effect` with \
+             \"amount\": 2) to `tiers`, then remove the old keys",
+        ));
+    }
+    serde_json::from_value(value).map_err(serde::de::Error::custom)
+}
+
+// Streamlabs loyalty store redemptions, keyed by reward title
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+struct RewardEffects {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    cooldown_ms: Option<u64>,
+    #[serde(default)]
+    rewards: HashMap<String, LightEffect>,
+}
+
+// Twitch channel point rewards, matched by reward id or name
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(from = "RedemptionsShape")]
+struct RedemptionEffects {
+    enabled: bool,
+    priority: i32,
+    cooldown_ms: Option<u64>,
+    rewards: Vec<RedemptionEffect>,
+}
+
+/// `twitch_redemptions` as written: the mapping, or a bare list of rewards as it used to be,
+/// which stays enabled.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RedemptionsShape {
+    List(Vec<RedemptionEffect>),
+    Mapping {
+        #[serde(default)]
+        enabled: bool,
+        #[serde(default)]
+        priority: i32,
+        #[serde(default, deserialize_with = "duration::deserialize_option")]
+        cooldown_ms: Option<u64>,
+        #[serde(default)]
+        rewards: Vec<RedemptionEffect>,
+    },
+}
+
+impl From<RedemptionsShape> for RedemptionEffects {
+    fn from(shape: RedemptionsShape) -> Self {
+        match shape {
+            RedemptionsShape::List(rewards) => Self { enabled: true, rewards, ..Default::default() },
+            RedemptionsShape::Mapping { enabled, priority, cooldown_ms, rewards } => Self { enabled, priority, cooldown_ms, rewards },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct RedemptionEffect {
+    #[serde(default)]
+    reward_id: Option<String>,
+    // Compared case-insensitively; `reward_id` survives renaming the reward
+    #[serde(default)]
+    reward_name: Option<String>,
+    effect: LightEffect,
+}
+
+impl RedemptionEffect {
+    fn matches(&self, reward: &EventPayload) -> bool {
+        let id_matches = self.reward_id.is_some() && self.reward_id == reward.id;
+        let name_matches = self.reward_name.as_ref().is_some_and(|name| name.eq_ignore_ascii_case(&reward.name));
+        id_matches || name_matches
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+struct EventTieredEffect {
+    enabled: bool,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    cooldown_ms: Option<u64>,
+    tiers: Vec<TierEffect>,
+    // `twitch_bits` amounts below this play no effect, whatever the tiers say
+    #[serde(default)]
+    min_amount: Option<f64>,
+    #[serde(default)]
+    continuous: Option<ContinuousMapping>,
+}
+
+// Scales brightness and duration linearly with the amount instead of stepping by tier
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct ContinuousMapping {
+    min_amount: f64,
+    max_amount: f64,
+    min_brightness: u8,
+    max_brightness: u8,
+    #[serde(deserialize_with = "duration::deserialize")]
+    min_duration: u64,
+    #[serde(deserialize_with = "duration::deserialize")]
+    max_duration: u64,
+    // Color and alert of the effect when there are no tiers; with tiers, the matching one's are used
+    #[serde(default)]
+    color: Option<String>,
+    #[serde(default)]
+    alert: Option<String>,
+}
+
+impl ContinuousMapping {
+    /// The effect to scale when `twitch_bits` has no tiers, if a color is set.
+    fn base_effect(&self) -> Option<LightEffect> {
+        let color = self.color.clone()?;
+        Some(LightEffect {
+            color,
+            alert: self.alert.clone().unwrap_or_else(|| "select".to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn apply(&self, effect: &mut LightEffect, amount: f64) {
+        let span = self.max_amount - self.min_amount;
+        let t = if span > 0.0 {
+            ((amount - self.min_amount) / span).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let lerp = |min: f64, max: f64| min + (max - min) * t;
+
+        effect.brightness = lerp(self.min_brightness as f64, self.max_brightness as f64).round() as u8;
+        effect.duration = lerp(self.min_duration as f64, self.max_duration as f64).round() as u64;
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct DonationEffect {
+    enabled: bool,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    cooldown_ms: Option<u64>,
+    tiers: Vec<TierEffect>,
+    // Donations below this play no effect, whatever the tiers say
+    #[serde(default)]
+    min_amount: Option<f64>,
+    // Currency code -> value of one unit in the currency the tiers are written in, e.g.
+    // `{"EUR": 1.08, "GBP": 1.27}` for USD tiers; other currencies are taken as is
+    #[serde(default)]
+    currency_rates: HashMap<String, f64>,
+    // Currency code (e.g. "EUR") -> color overriding the tier color
+    #[serde(default)]
+    currency_colors: HashMap<String, String>,
+    // Use the first color name in the donor's message, if any, instead of the tier color
+    #[serde(default)]
+    message_color_override: bool,
+}
+
+impl DonationEffect {
+    /// Converts an amount in `currency` to the tiers' currency using `currency_rates`.
+    fn normalize(&self, amount: f64, currency: Option<&str>) -> f64 {
+        let rate = currency.and_then(|currency| {
+            self.currency_rates.iter().find(|(code, _)| code.eq_ignore_ascii_case(currency))
+        });
+        match rate {
+            Some((code, rate)) => {
+                debug!("Converted {} {} at {} to {}", amount, code, rate, amount * rate);
+                amount * rate
+            }
+            None => amount,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct TierEffect {
+    amount: f64,
+    effect: LightEffect,
+}
+
+// Shifts tier selection up on quiet streams and down during event floods
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct AdaptiveTierConfig {
+    enabled: bool,
+    #[serde(deserialize_with = "duration::deserialize")]
+    window_ms: u64,
+    quiet_max_events: usize,
+    busy_min_events: usize,
+    steps: usize,
+}
+
+impl Default for AdaptiveTierConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_ms: 60_000,
+            quiet_max_events: 1,
+            busy_min_events: 10,
+            steps: 1,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+struct LightEffect {
+    // May be left out when `color_temp_mirek` is set
+    #[serde(default)]
+    color: String,
+    brightness: u8,
+    alert: String,
+    #[serde(deserialize_with = "duration::deserialize")]
+    duration: u64,
+    // Re-trigger "lselect" so the breathe lasts the whole duration
+    #[serde(default)]
+    sustain_alert: bool,
+    // Pick this many random lights each time instead of all of them
+    #[serde(default)]
+    random_light_count: Option<usize>,
+    // Fade to the color over this long before firing the alert
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    fade_in_ms: Option<u64>,
+    // Name of a config `light_groups` entry to limit the effect to
+    #[serde(default)]
+    light_group: Option<String>,
+    // Bridge light ids to limit the effect to
+    #[serde(default)]
+    target_lights: Option<Vec<usize>>,
+    // Name (or id) of a group or room on the bridge, commanded with a single group request
+    #[serde(default)]
+    target_group: Option<String>,
+    // Turn lights that were off before the effect back off instead of restoring the default state
+    #[serde(default)]
+    restore_off: bool,
+    // Put each light back into the state it had before the effect instead of `default_state`
+    #[serde(default)]
+    restore_previous: bool,
+    // Keep the color (without the alert) this long after `duration` before resetting
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    hold_after_ms: Option<u64>,
+    // Light `mirror_pairs` one pair at a time instead of all lights at once
+    #[serde(default)]
+    sweep: Option<SweepMode>,
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    sweep_step_ms: Option<u64>,
+    // Fade into the effect and back to the default state over this long instead of snapping
+    #[serde(default, deserialize_with = "duration::deserialize_option")]
+    transition_ms: Option<u64>,
+    // How far to blend `color` toward the config `theme_color`, from 0.0 (none) to 1.0 (all)
+    #[serde(default)]
+    theme_blend: Option<f64>,
+    // Alternate between the color and off (or `second_color`) for the whole duration
+    #[serde(default)]
+    strobe: Option<StrobeConfig>,
+    // Sweep the hue once around the color wheel over the duration
+    #[serde(default)]
+    rainbow: Option<RainbowConfig>,
+    // Send the color as CIE xy (`"xy"`) for truer colors, or as hue/saturation (default)
+    #[serde(default)]
+    color_mode: ColorMode,
+    // Set a white color temperature (153-500 mirek) instead of a color
+    #[serde(default)]
+    color_temp_mirek: Option<u16>,
+    // A queued effect with a higher priority interrupts this one while it plays
+    #[serde(default)]
+    priority: i32,
+    // Drop every other effect waiting in the queue and play this one next
+    #[serde(default)]
+    flush_queue: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ColorMode {
+    #[default]
+    HueSat,
+    Xy,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+struct RainbowConfig {
+    steps: u32,
+}
+
+impl Default for RainbowConfig {
+    fn default() -> Self {
+        Self { steps: 36 }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct StrobeConfig {
+    #[serde(deserialize_with = "duration::deserialize")]
+    interval_ms: u64,
+    #[serde(default)]
+    second_color: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SweepMode {
+    CenterOut,
+    EdgeIn,
+}
+
+// Updated Streamlabs event structures
+#[derive(Debug, Deserialize)]
+struct StreamlabsEvent {
+    event_id: String,
+    #[serde(rename = "for")]
+    event_for: Option<String>,
+    #[serde(rename = "type")]
+    event_type: String,
+    message: Vec<EventMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventMessage {
+    #[serde(default)]
+    name: String,
+    // Text the donor attached to a donation
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    amount: Option<String>,
+    #[serde(default)]
+    formatted_amount: Option<String>,
+    #[serde(default)]
+    currency: Option<String>,
+    // Reward title on loyalty store redemptions
+    #[serde(default)]
+    product: Option<String>,
+    // Cumulative months, "resub"/"subgift" marker and gifter name on subscriptions
+    #[serde(default)]
+    months: Option<u32>,
+    #[serde(default)]
+    sub_type: Option<String>,
+    #[serde(default)]
+    gifter: Option<String>,
+    // Subs gifted at once, on community gifts
+    #[serde(default, deserialize_with = "deserialize_count")]
+    gift_count: Option<u64>,
+    #[serde(default)]
+    _id: String,
+    // Channel point reward on Twitch redemptions
+    payload: Option<EventPayload>,
+    // Viewers brought along by a raid or host
+    #[serde(default, deserialize_with = "deserialize_count")]
+    raiders: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_count")]
+    viewers: Option<u64>,
+}
+
+/// Reads a count sent either as a JSON number or as a numeric string. Text that isn't a number
+/// reads as no count, so the event is still handled rather than failing to parse.
+fn deserialize_count<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Count {
+        Number(u64),
+        Text(String),
+    }
+    match Option::<Count>::deserialize(deserializer)? {
+        Some(Count::Number(count)) => Ok(Some(count)),
+        Some(Count::Text(text)) => Ok(text.trim().parse().ok()),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EventPayload {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct HandledEffect {
+    effect: Option<LightEffect>,
+    // Amount of the selected tier, for tiered events
+    tier: Option<f64>,
+    // The event's amount as its handler read it, in whole units of its currency (or bits)
+    amount: Option<f64>,
+}
+
+/// Notes the amount the current event's handler read, for `publish_event`.
+fn record_amount(amount: f64) {
+    let _ = HANDLED_EFFECT.try_with(|handled| handled.borrow_mut().amount = Some(amount));
+}
+
+// The most recent effect that finished, for the dashboard
+#[derive(Debug, Clone, Serialize)]
+struct AppliedEffect {
+    finished_at: DateTime<Local>,
+    effect: LightEffect,
+    success: bool,
+}
+
+// One handled event in the recent history
+#[derive(Debug, Clone, Serialize)]
+struct EventRecord {
+    timestamp: DateTime<Local>,
+    event_type: String,
+    donor: Option<String>,
+    amount: Option<String>,
+    effect_color: Option<String>,
+    success: bool,
+}
+
+impl EventRecord {
+    const CSV_HEADER: &'static str = "timestamp,type,donor,amount,effect_color,success";
+
+    fn to_csv_row(&self) -> String {
+        [
+            self.timestamp.to_rfc3339(),
+            self.event_type.clone(),
+            self.donor.clone().unwrap_or_default(),
+            self.amount.clone().unwrap_or_default(),
+            self.effect_color.clone().unwrap_or_default(),
+            self.success.to_string(),
+        ]
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+/// Quotes a CSV field when it contains a delimiter, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Console commands sent from the main thread to the running app
+enum Control {
+    ResetLights,
+    // Sent by the socket callbacks when the Streamlabs connection drops
+    SocketLost,
+    Shutdown,
+}
+
+struct AppState {
+    // The Hue bridge, unless a test swapped in another backend
+    controller: Arc<Mutex<Box<dyn LightController>>>,
+    config: RwLock<Arc<Config>>,
+    recent_events: Mutex<VecDeque<Instant>>,
+    rng: Mutex<StdRng>,
+    bridge_failures: AtomicU32,
+    // Set once a bridge command fails, cleared when one succeeds again
+    bridge_lost: AtomicBool,
+    // Cleared while the Streamlabs socket is down and being reconnected
+    streamlabs_connected: AtomicBool,
+    last_effect: Mutex<Option<AppliedEffect>>,
+    last_reconnect_flash: Mutex<Option<Instant>>,
+    // Light ids the bridge reported the last time lights were listed
+    known_lights: Mutex<HashSet<usize>>,
+    first_event_seen: AtomicBool,
+    last_parse_error_flash: Mutex<Option<Instant>>,
+    monthly_total: Mutex<MonthlyTotal>,
+    // Donations plus converted bits since startup
+    session_total: Mutex<f64>,
+    // When the last effect finished; None while one is running or once the watchdog has checked
+    last_effect_end: Mutex<Option<Instant>>,
+    // Earliest start of the next effect when a cooldown buffer is configured
+    cooldown_until: Mutex<Option<Instant>>,
+    // Ids of the most recent events, oldest first, to skip ones Streamlabs sends twice
+    seen_event_ids: Mutex<VecDeque<String>>,
+    // When each event type last played an effect, for per-event cooldowns. On tokio's clock, which
+    // paused tests can move through a cooldown
+    last_fired: Mutex<HashMap<String, tokio::time::Instant>>,
+    event_history: Mutex<VecDeque<EventRecord>>,
+    next_event_id: AtomicU64,
+    // Types of recent events that played an effect, for `hype_moments`
+    hype_events: Mutex<VecDeque<(Instant, String)>>,
+    // Set on shutdown so long-running effect loops stop early
+    shutting_down: AtomicBool,
+    effect_queue: EffectQueue,
+    // Set in main when `mqtt.enabled`
+    mqtt: Mutex<Option<mqtt::MqttPublisher>>,
+    // Set in main when `discord.enabled`
+    discord: Mutex<Option<discord::DiscordNotifier>>,
+    metrics: metrics::Metrics,
+}
+
+/// Effects waiting to be played. Event handlers push onto it and return right away;
+/// `AppState::run_effect_queue` applies them one at a time.
+struct EffectQueue {
+    sender: mpsc::UnboundedSender<QueuedEffect>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<QueuedEffect>>>,
+    next_sequence: AtomicU64,
+}
+
+struct QueuedEffect {
+    effect: LightEffect,
+    // Arrival order, which reordering by priority loses
+    sequence: u64,
+    // Unset for effects not triggered by an event, such as API previews and schedules
+    source: Option<EffectSource>,
+}
+
+/// The event an effect was queued for.
+#[derive(Debug, Clone)]
+struct EffectSource {
+    // Increments per handled event, so effects queued by one event stay together
+    event_id: u64,
+    event_type: String,
+    event_for: Option<String>,
+    // Configured event priority plus monetary value, for `queue_ordering: "priority"`
+    score: f64,
+}
+
+impl EffectQueue {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            next_sequence: AtomicU64::new(0),
+        }
+    }
+
+    fn push(&self, effect: LightEffect, source: Option<EffectSource>) -> Result<(), AppError> {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        self.sender.send(QueuedEffect { effect, sequence, source })
+            .map_err(|_| AppError::Bridge("Effect queue is closed".to_string()))
+    }
+
+    /// The consuming end, handed out once to the task that plays the effects.
+    fn take_receiver(&self) -> Option<mpsc::UnboundedReceiver<QueuedEffect>> {
+        self.receiver.lock().take()
+    }
+}
+
+/// A color field split by syntax; `Rgb` channels are in `0.0..=1.0`, `Hsl` is degrees plus fractions.
+enum ColorSpec<'a> {
+    Hex(&'a str),
+    Rgb(f32, f32, f32),
+    Hsl(f32, f32, f32),
+}
+
+/// Recognizes a CSS color name, hex code, `rgb(r, g, b)` or `hsl(h, s%, l%)`.
+fn color_spec(color: &str) -> Result<ColorSpec<'_>, AppError> {
+    if let Some(hex) = colors::named_color(color) {
+        return Ok(ColorSpec::Hex(hex));
+    }
+    if let Some(components) = color_function(color, "rgb") {
+        let (r, g, b) = rgb_components(color, components)?;
+        return Ok(ColorSpec::Rgb(r, g, b));
+    }
+    if let Some(components) = color_function(color, "hsl") {
+        let (h, s, l) = hsl_components(color, components)?;
+        return Ok(ColorSpec::Hsl(h, s, l));
+    }
+    let hex = color.trim().trim_start_matches('#');
+    if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::Bridge(format!(
+            "Unrecognized color name {:?}, expected a CSS color name, a hex code, rgb() or hsl()",
+            color
+        )));
+    }
+    Ok(ColorSpec::Hex(hex))
+}
+
+/// Parses any supported color syntax into Hue's hue and saturation.
+fn parse_color(color: &str) -> Result<(u16, u8), AppError> {
+    match color_spec(color)? {
+        ColorSpec::Hex(hex) => hex_to_hue(hex),
+        ColorSpec::Rgb(r, g, b) => Ok(rgb_to_hue(r, g, b)),
+        ColorSpec::Hsl(h, s, l) => Ok(hsl_to_hue(h, s, l)),
+    }
+}
+
+/// Parses any supported color syntax into sRGB channels in `0.0..=1.0`.
+fn parse_color_rgb(color: &str) -> Result<(f32, f32, f32), AppError> {
+    match color_spec(color)? {
+        ColorSpec::Hex(hex) => hex_to_rgb(hex),
+        ColorSpec::Rgb(r, g, b) => Ok((r, g, b)),
+        ColorSpec::Hsl(h, s, l) => Ok(hsl_to_rgb(h, s, l)),
+    }
+}
+
+/// Parses any supported color syntax into CIE xy coordinates for `color_mode: "xy"`.
+fn parse_color_xy(color: &str) -> Result<[f32; 2], AppError> {
+    match color_spec(color)? {
+        ColorSpec::Hex(hex) => hex_to_xy(hex),
+        ColorSpec::Rgb(r, g, b) => Ok(rgb_to_xy(r, g, b)),
+        ColorSpec::Hsl(h, s, l) => {
+            let (r, g, b) = hsl_to_rgb(h, s, l);
+            Ok(rgb_to_xy(r, g, b))
+        }
+    }
+}
+
+/// The comma-separated arguments of `name(...)`, matched case-insensitively.
+fn color_function<'a>(color: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let color = color.trim();
+    let prefix = color.get(..name.len() + 1)?;
+    if !prefix.eq_ignore_ascii_case(&format!("{}(", name)) {
+        return None;
+    }
+    let inner = color[prefix.len()..].strip_suffix(')')?;
+    Some(inner.split(',').map(str::trim).collect())
+}
+
+/// Parses one color function argument, rejecting values outside `0.0..=max`.
+fn color_component(color: &str, component: &str, max: f32) -> Result<f32, AppError> {
+    let value: f32 = component.parse().map_err(|_| {
+        AppError::Bridge(format!("Invalid color component {:?} in {:?}", component, color))
+    })?;
+    if !(0.0..=max).contains(&value) {
+        return Err(AppError::Bridge(format!(
+            "Color component {} in {:?} is outside 0-{}",
+            value, color, max
+        )));
+    }
+    Ok(value)
+}
+
+fn rgb_components(color: &str, components: Vec<&str>) -> Result<(f32, f32, f32), AppError> {
+    let [r, g, b] = components[..] else {
+        return Err(AppError::Bridge(format!("Expected rgb(r, g, b), got {:?}", color)));
+    };
+    Ok((
+        color_component(color, r, 255.0)? / 255.0,
+        color_component(color, g, 255.0)? / 255.0,
+        color_component(color, b, 255.0)? / 255.0,
+    ))
+}
+
+/// Hue in degrees plus saturation and lightness in `0.0..=1.0`.
+fn hsl_components(color: &str, components: Vec<&str>) -> Result<(f32, f32, f32), AppError> {
+    let [h, s, l] = components[..] else {
+        return Err(AppError::Bridge(format!("Expected hsl(h, s%, l%), got {:?}", color)));
+    };
+    let percent = |component: &str| -> Result<f32, AppError> {
+        let value = component.strip_suffix('%').ok_or_else(|| {
+            AppError::Bridge(format!("Expected a percentage, got {:?} in {:?}", component, color))
+        })?;
+        Ok(color_component(color, value, 100.0)? / 100.0)
+    };
+    let hue = color_component(color, h.trim_end_matches("deg"), 360.0)?;
+    Ok((hue, percent(s)?, percent(l)?))
+}
+
+fn hsl_to_hue(hue: f32, saturation: f32, lightness: f32) -> (u16, u8) {
+    // HSL to HSV: the value is the brightest channel, the saturation is relative to it
+    let value = lightness + saturation * lightness.min(1.0 - lightness);
+    let saturation = if value == 0.0 { 0.0 } else { 2.0 * (1.0 - lightness / value) };
+
+    ((hue / 360.0 * 65535.0) as u16, (saturation * 254.0) as u8)
+}
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = (hue % 360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    (r + m, g + m, b + m)
+}
+
+/// Converts a hex color to CIE xy in the Philips wide gamut, which reproduces colors on Hue bulbs
+/// more faithfully than hue/saturation.
+fn hex_to_xy(hex: &str) -> Result<[f32; 2], AppError> {
+    let (r, g, b) = hex_to_rgb(hex)?;
+    Ok(rgb_to_xy(r, g, b))
+}
+
+/// sRGB channels in `0.0..=1.0` to CIE xy: undo the sRGB gamma, then the Philips wide gamut
+/// RGB->XYZ matrix (D65). Black has no chromaticity and maps to the white point.
+fn rgb_to_xy(r: f32, g: f32, b: f32) -> [f32; 2] {
+    let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+
+    let x = r * 0.664_511 + g * 0.154_324 + b * 0.162_028;
+    let y = r * 0.283_881 + g * 0.668_433 + b * 0.047_685;
+    let z = r * 0.000_088 + g * 0.072_310 + b * 0.986_039;
+    let sum = x + y + z;
+    if sum == 0.0 {
+        return [0.3127, 0.3290];
+    }
+    [x / sum, y / sum]
+}
+
+/// Converts a hex color to Hue's hue (0-65535) and saturation (0-254).
+fn hex_to_hue(hex: &str) -> Result<(u16, u8), AppError> {
+    let (r, g, b) = hex_to_rgb(hex)?;
+    Ok(rgb_to_hue(r, g, b))
+}
+
+fn hex_to_rgb(hex: &str) -> Result<(f32, f32, f32), AppError> {
+    let hex = hex.trim_start_matches('#');
+    let rgb = Vec::from_hex(hex)
+        .map_err(|e| AppError::Bridge(format!("Invalid hex color: {}", e)))?;
+    
+    if rgb.len() != 3 {
+        return Err(AppError::Bridge("Invalid RGB values".to_string()));
+    }
+    
+    Ok((rgb[0] as f32 / 255.0, rgb[1] as f32 / 255.0, rgb[2] as f32 / 255.0))
+}
+
+/// The sRGB transfer function undone, so a channel is proportional to emitted light.
+fn srgb_to_linear(c: f32) -> f32 {
+    if c > 0.04045 {
+        ((c + 0.055) / 1.055).powf(2.4)
+    } else {
+        c / 12.92
+    }
+}
+
+/// Converts sRGB channels in `0.0..=1.0` to Hue's hue (0-65535) and saturation (0-254),
+/// gamma-decoding them first since the bulbs mix linear light.
+fn rgb_to_hue(r: f32, g: f32, b: f32) -> (u16, u8) {
+    let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * (((g - b) / delta) % 6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    
+    let hue = ((hue / 360.0) * 65535.0) as u16;
+    let saturation = (saturation * 254.0) as u8;
+    
+    (hue, saturation)
+}
+
+/// Whether a light already shows `default_state`; color is ignored while the default is off.
+fn in_default_state(state: &hueclient::LightState, default_state: &LightState) -> bool {
+    state.on == default_state.on
+        && (!default_state.on || (state.bri == Some(default_state.brightness)
+            && state.hue == Some(default_state.hue)
+            && state.sat == Some(default_state.saturation)))
+}
+
+fn check_color_temp(mirek: u16) -> Result<(), AppError> {
+    if !COLOR_TEMP_MIREK_RANGE.contains(&mirek) {
+        return Err(AppError::Config(format!(
+            "color_temp_mirek {} is outside the supported {}-{} range",
+            mirek,
+            COLOR_TEMP_MIREK_RANGE.start(),
+            COLOR_TEMP_MIREK_RANGE.end()
+        )));
+    }
+    Ok(())
+}
+
+/// Ids in `known` that are missing from `live`, in ascending order.
+fn removed_lights(known: &HashSet<usize>, live: &HashSet<usize>) -> Vec<usize> {
+    let mut removed: Vec<usize> = known.difference(live).copied().collect();
+    removed.sort_unstable();
+    removed
+}
+
+/// Command that puts a light back the way it was read before an effect, or `None` when the
+/// bridge reported nothing usable for a light that was on.
+fn previous_state_command(state: &hueclient::LightState) -> Option<CommandLight> {
+    if !state.on {
+        return Some(CommandLight {
+            on: Some(false),
+            ..Default::default()
+        });
+    }
+    if state.bri.is_none() && state.hue.is_none() && state.sat.is_none() && state.ct.is_none() {
+        return None;
+    }
+
+    let has_color = state.hue.is_some() && state.sat.is_some();
+    Some(CommandLight {
+        on: Some(true),
+        bri: state.bri,
+        hue: state.hue.filter(|_| has_color),
+        sat: state.sat.filter(|_| has_color),
+        ct: state.ct.filter(|_| !has_color),
+        alert: Some("none".to_string()),
+        ..Default::default()
+    })
+}
+
+/// Mixes two colors of any supported syntax in sRGB, `t` of the way from `from` to `to`,
+/// as a hex code.
+fn blend_colors(from: &str, to: &str, t: f64) -> Result<String, AppError> {
+    let (from, to) = (parse_color_rgb(from)?, parse_color_rgb(to)?);
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: f32, b: f32| ((a as f64 + (b as f64 - a as f64) * t) * 255.0).round() as u8;
+    Ok(format!("#{}", hex::encode([mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2)])))
+}
+
+/// The command an effect sends before per-light scaling; `brightness: 0` turns the lights off.
+fn effect_command(effect: &LightEffect, hue: u16, sat: u8) -> CommandLight {
+    let transitiontime = effect.transition_ms.map(transition_time);
+    if effect.brightness == 0 {
+        return CommandLight {
+            on: Some(false),
+            transitiontime,
+            ..Default::default()
+        };
+    }
+    CommandLight {
+        on: Some(true),
+        bri: Some(effect.brightness),
+        hue: Some(hue),
+        sat: Some(sat),
+        alert: Some(effect.alert.clone()),
+        transitiontime,
+        ..Default::default()
+    }
+}
+
+/// Pairs each light with the sweep step it lights up in. Without a sweep every light is step 0;
+/// with one, lights outside `mirror_pairs` go first and each pair gets its own step.
+fn sweep_order<'a>(
+    sweep: Option<SweepMode>,
+    pairs: &[(usize, usize)],
+    lights: &'a [IdentifiedLight],
+) -> Vec<(usize, &'a IdentifiedLight)> {
+    let Some(mode) = sweep else {
+        return lights.iter().map(|light| (0, light)).collect();
+    };
+
+    let paired = |id: usize| pairs.iter().any(|&(left, right)| id == left || id == right);
+    let mut order: Vec<(usize, &IdentifiedLight)> = lights
+        .iter()
+        .filter(|light| !paired(light.id))
+        .map(|light| (0, light))
+        .collect();
+
+    let mut steps: Vec<&(usize, usize)> = pairs.iter().collect();
+    if mode == SweepMode::EdgeIn {
+        steps.reverse();
+    }
+    for (i, &&(left, right)) in steps.iter().enumerate() {
+        let step = i + 1;
+        order.extend(lights.iter().filter(|light| light.id == left || light.id == right).map(|light| (step, light)));
+    }
+    order
+}
+
+/// Multiplies a command's brightness and saturation, keeping brightness at least 1.
+fn scale_command(command: &CommandLight, bri_factor: f64, sat_factor: f64) -> CommandLight {
+    let scale = |value: u8, factor: f64, min: f64| (value as f64 * factor).round().clamp(min, 254.0) as u8;
+    CommandLight {
+        bri: command.bri.map(|bri| scale(bri, bri_factor, 1.0)),
+        sat: command.sat.map(|sat| scale(sat, sat_factor, 0.0)),
+        ..command.clone()
+    }
+}
+
+/// Converts milliseconds to the bridge's `transitiontime` unit of 100 ms.
+fn transition_time(ms: u64) -> u16 {
+    (ms / 100).min(u16::MAX as u64) as u16
+}
+
+/// Index of the highest tier `amount` reaches, falling back to the lowest tier.
+fn tier_index(tiers: &[TierEffect], amount: f64) -> Option<usize> {
+    let by_amount = |a: &(usize, &TierEffect), b: &(usize, &TierEffect)| {
+        a.1.amount.total_cmp(&b.1.amount)
+    };
+    tiers.iter().enumerate()
+        .filter(|(_, tier)| amount >= tier.amount)
+        .max_by(by_amount)
+        .or_else(|| tiers.iter().enumerate().min_by(by_amount))
+        .map(|(index, _)| index)
+}
+
+/// Reads a donation's amount from `amount`, falling back to `formatted_amount`.
+fn donation_amount(message: &EventMessage) -> Result<f64, AppError> {
+    let fields = [message.amount.as_deref(), message.formatted_amount.as_deref()];
+    fields.iter().flatten().find_map(|raw| parse_amount(raw)).ok_or_else(|| {
+        let raw = fields.iter().flatten().next().copied().unwrap_or_default();
+        AppError::InvalidAmount(raw.to_string())
+    })
+}
+
+/// Parses an amount such as `"5"`, `"£5.00"`, `"1,234.56"`, `"5,00 €"` or `"1.234,56"`, ignoring
+/// currency symbols and codes. The last separator is the decimal point unless it repeats
+/// (`"1,234,567"`), or is the only separator and has exactly three digits after it (`"1,234"`).
+fn parse_amount(raw: &str) -> Option<f64> {
+    let number: String = raw.chars().filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-')).collect();
+    let number = number.trim_matches(|c| c == '.' || c == ',');
+    if let Ok(amount) = number.parse::<f64>() {
+        return Some(amount);
+    }
+
+    let decimal = number.rfind(['.', ',']).filter(|&i| {
+        let separator = if number.as_bytes()[i] == b'.' { '.' } else { ',' };
+        let other = if separator == '.' { ',' } else { '.' };
+        number.matches(separator).count() == 1 && (number.contains(other) || number.len() - i - 1 != 3)
+    });
+    let normalized: String = number.char_indices()
+        .filter_map(|(i, c)| match c {
+            '.' | ',' if Some(i) == decimal => Some('.'),
+            '.' | ',' => None,
+            c => Some(c),
+        })
+        .collect();
+    normalized.parse().ok()
+}
+
+/// Currency code of a donation, from the explicit field or the symbol in `formatted_amount`.
+fn donation_currency(message: &EventMessage) -> Option<String> {
+    if let Some(currency) = message.currency.as_deref().filter(|c| !c.is_empty()) {
+        return Some(currency.to_uppercase());
+    }
+
+    let formatted = message.formatted_amount.as_deref()?;
+    let symbol = match formatted.chars().find(|c| !c.is_ascii_digit() && !matches!(c, '.' | ',' | ' ' | '-')) {
+        Some('$') => "USD",
+        Some('€') => "EUR",
+        Some('£') => "GBP",
+        Some('¥') => "JPY",
+        Some('₹') => "INR",
+        Some('₩') => "KRW",
+        _ => {
+            // Fall back to a three-letter code such as "5.00 CAD"
+            return formatted
+                .split(|c: char| !c.is_ascii_alphabetic())
+                .find(|word| word.len() == 3)
+                .map(str::to_uppercase);
+        }
+    };
+    Some(symbol.to_string())
+}
+
+impl AppState {
+    fn new(controller: impl LightController + 'static, config: Config) -> Self {
+        let monthly_total = if config.monthly_goal.enabled {
+            MonthlyTotal::load(&config.monthly_goal.state_file)
+        } else {
+            MonthlyTotal::default()
+        };
+        let rng = match config.random_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            controller: Arc::new(Mutex::new(Box::new(controller))),
+            config: RwLock::new(Arc::new(config)),
+            recent_events: Mutex::new(VecDeque::new()),
+            rng: Mutex::new(rng),
+            bridge_failures: AtomicU32::new(0),
+            bridge_lost: AtomicBool::new(false),
+            streamlabs_connected: AtomicBool::new(false),
+            last_effect: Mutex::new(None),
+            known_lights: Mutex::new(HashSet::new()),
+            last_reconnect_flash: Mutex::new(None),
+            first_event_seen: AtomicBool::new(false),
+            last_parse_error_flash: Mutex::new(None),
+            monthly_total: Mutex::new(monthly_total),
+            session_total: Mutex::new(0.0),
+            last_effect_end: Mutex::new(None),
+            cooldown_until: Mutex::new(None),
+            seen_event_ids: Mutex::new(VecDeque::new()),
+            last_fired: Mutex::new(HashMap::new()),
+            event_history: Mutex::new(VecDeque::with_capacity(EVENT_HISTORY_LEN)),
+            next_event_id: AtomicU64::new(0),
+            hype_events: Mutex::new(VecDeque::new()),
+            shutting_down: AtomicBool::new(false),
+            effect_queue: EffectQueue::new(),
+            mqtt: Mutex::new(None),
+            discord: Mutex::new(None),
+            metrics: metrics::Metrics::default(),
+        }
+    }
+
+    /// Snapshot of the live configuration; edits swap in a new `Arc`.
+    fn config(&self) -> Arc<Config> {
+        self.config.read().clone()
+    }
+
+    /// Re-reads the config file and swaps it in, keeping the current config if it fails to load
+    /// or validate. Credentials and dry-run mode stay as they were until a restart, and a show
+    /// switched to at runtime stays active.
+    fn reload_config(&self) {
+        let current = self.config();
+        let mut config = match load_config(&current.path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Keeping the current config, {} failed to reload: {}", current.path, e);
+                return;
+            }
+        };
+
+        if serde_json::to_value(&config.credentials).ok() != serde_json::to_value(&current.credentials).ok() {
+            warn!("Credential changes in {} take effect after a restart", current.path);
+        }
+        config.credentials = current.credentials.clone();
+        config.dry_run = current.dry_run;
+        config.backend = current.backend;
+        if let Some(show) = current.active_show.as_ref().filter(|show| config.shows.contains_key(*show)) {
+            config.active_show = Some(show.clone());
+        }
+
+        *self.config.write() = Arc::new(config);
+        info!("Reloaded {}", current.path);
+        if let Err(e) = self.validate_light_groups() {
+            error!("Failed to validate light groups: {}", e);
+        }
+    }
+
+    async fn handle_event(&self, event: StreamlabsEvent) -> Result<(), AppError> {
+        info!("Processing event: {:?}", event);
+        self.metrics.event_received(&event.event_type);
+        if self.is_duplicate(&event.event_id) {
+            info!("Ignoring duplicate {} event {}", event.event_type, event.event_id);
+            self.metrics.event_ignored("duplicate");
+            return Ok(());
+        }
+        let config = self.config();
+        let events = config.active_events();
+        // Only adaptive tiers read the event rate, and they prune the times as they do
+        if config.adaptive_tiers.enabled {
+            self.recent_events.lock().push_back(Instant::now());
+        }
+
+        let cooldown_ms = events.cooldown_ms(&event.event_type).or(config.event_cooldown_ms);
+        if let Some(cooldown_ms) = cooldown_ms {
+            let since = self.last_fired.lock().get(&event.event_type).map(tokio::time::Instant::elapsed);
+            if let Some(since) = since.filter(|since| *since < Duration::from_millis(cooldown_ms)) {
+                info!("Ignoring {} event {} ms into its {} ms cooldown", event.event_type, since.as_millis(), cooldown_ms);
+                self.metrics.event_ignored("cooldown");
+                return Ok(());
+            }
+        }
+
+        let source = EffectSource {
+            event_id: self.next_event_id.fetch_add(1, Ordering::Relaxed),
+            event_type: event.event_type.clone(),
+            event_for: event.event_for.clone(),
+            score: events.priority(&event.event_type) as f64 + self.event_value(&event),
+        };
+        let handled = HANDLED_EFFECT.scope(RefCell::new(HandledEffect::default()), async {
+            let result = self.dispatch_event(&event, events).await;
+            (result, HANDLED_EFFECT.with(|handled| handled.take()))
+        });
+        let (result, handled) = EFFECT_SOURCE.scope(source, handled).await;
+
+        if let Err(e) = &result {
+            error!("Error processing event: {}", e);
+        }
+        let played = result.is_ok() && handled.effect.is_some();
+        self.publish_event(&event, &handled, result.is_ok());
+        self.record_event(&event, handled.effect.map(|effect| effect.color), result.is_ok());
+
+        if played {
+            self.last_fired.lock().insert(event.event_type.clone(), tokio::time::Instant::now());
+            if let Some(moment) = self.match_hype_moment(&event.event_type) {
+                info!("Hype moment {:?}!", moment.name);
+                if let Err(e) = self.enqueue_effect(&moment.effect) {
+                    error!("Failed to queue hype moment {:?} effect: {}", moment.name, e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Remembers `event_id`, returning whether it was already among the recent ids.
+    fn is_duplicate(&self, event_id: &str) -> bool {
+        let capacity = self.config().dedup_buffer_size.unwrap_or(DEFAULT_DEDUP_BUFFER_SIZE);
+        if capacity == 0 || event_id.is_empty() {
+            return false;
+        }
+        let mut seen = self.seen_event_ids.lock();
+        if seen.iter().any(|id| id == event_id) {
+            return true;
+        }
+        while seen.len() >= capacity {
+            seen.pop_front();
+        }
+        seen.push_back(event_id.to_string());
+        false
+    }
+
+    async fn dispatch_event(&self, event: &StreamlabsEvent, events: &EventConfig) -> Result<(), AppError> {
+        match (event.event_type.as_str(), event.event_for.as_deref()) {
+            ("donation", None) if events.donation.enabled => {
+                info!("Handling donation event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_donation(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("follow", Some("twitch_account")) if events.twitch_follow.enabled => {
+                info!("Handling Twitch follow event");
+                self.with_first_event(self.handle_twitch_follow(events)).await
+            },
+            ("subscription" | "resub", Some("twitch_account"))
+                if events.twitch_subscription.enabled || events.twitch_gift_subscription.enabled => {
+                info!("Handling Twitch subscription event");
+                self.with_first_event(self.handle_twitch_subscription(event, events)).await
+            },
+            ("subMysteryGift", Some("twitch_account")) if events.twitch_gift_subscription.enabled => {
+                info!("Handling Twitch community gift event");
+                self.with_first_event(self.handle_gift_subscription(event.message.first(), events)).await
+            },
+            ("bits", Some("twitch_account")) if events.twitch_bits.enabled => {
+                info!("Handling Twitch bits event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_bits(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("raid", Some("twitch_account")) if events.twitch_raid.enabled => {
+                info!("Handling Twitch raid event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_raid(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("host", Some("twitch_account")) if events.twitch_host.enabled => {
+                info!("Handling Twitch host event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_host(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("redemption", Some("twitch_account")) if events.twitch_redemptions.enabled => {
+                info!("Handling Twitch channel point redemption event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_twitch_redemption(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("loyalty_store_redemption", Some("streamlabs")) if events.streamlabs_loyalty.enabled => {
+                info!("Handling Streamlabs loyalty redemption event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_loyalty_redemption(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("supersticker", Some("youtube_account")) if events.youtube_super_sticker.enabled => {
+                info!("Handling YouTube Super Sticker event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_super_sticker(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("superchat", Some("youtube_account")) if events.youtube_superchat.enabled => {
+                info!("Handling YouTube Super Chat event");
+                self.with_first_event(async {
+                    if let Some(message) = event.message.first() {
+                        self.handle_superchat(message, events).await?;
+                    }
+                    Ok(())
+                }).await
+            },
+            ("membership", Some("youtube_account")) if events.youtube_membership.enabled => {
+                info!("Handling YouTube membership event");
+                self.with_first_event(self.handle_youtube_membership(events)).await
+            },
+            (_, platform) if is_unknown_platform(platform) => {
+                self.handle_unknown_platform(event).await
+            },
+            _ => {
+                info!("Unhandled or disabled event: type={}, for={:?}", 
+                      event.event_type, event.event_for);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends the event, with the tier and effect it played, to the MQTT topic and Discord
+    /// webhook when they're enabled. Neither waits for the message to go out.
+    fn publish_event(&self, event: &StreamlabsEvent, handled: &HandledEffect, success: bool) {
+        let mqtt = self.mqtt.lock();
+        let discord = self.discord.lock();
+        if mqtt.is_none() && discord.is_none() {
+            return;
+        }
+        let message = event_message(event, handled, success);
+        if let Some(publisher) = &*mqtt {
+            publisher.publish(&message);
+        }
+        if let Some(notifier) = &*discord {
+            notifier.notify(&message);
+        }
+    }
+
+    fn record_event(&self, event: &StreamlabsEvent, effect_color: Option<String>, success: bool) {
+        let message = event.message.first();
+        let record = EventRecord {
+            timestamp: Local::now(),
+            event_type: event.event_type.clone(),
+            donor: message.map(|m| m.name.clone()).filter(|name| !name.is_empty()),
+            amount: message.and_then(|m| m.amount.clone()),
+            effect_color,
+            success,
+        };
+
+        let mut history = self.event_history.lock();
+        if history.len() == EVENT_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(record);
+    }
+
+    /// Records an event type that played an effect and returns the first hype moment it completes.
+    /// The events that made up the moment are consumed so it doesn't fire again right away.
+    fn match_hype_moment(&self, event_type: &str) -> Option<HypeMoment> {
+        let config = self.config();
+        if config.hype_moments.is_empty() {
+            return None;
+        }
+
+        let longest = config.hype_moments.iter().map(|moment| moment.window_ms).max().unwrap_or(0);
+        let mut recent = self.hype_events.lock();
+        recent.push_back((Instant::now(), event_type.to_string()));
+        while recent.front().is_some_and(|(at, _)| at.elapsed() > Duration::from_millis(longest)) {
+            recent.pop_front();
+        }
+
+        let moment = config.hype_moments.iter().find(|moment| {
+            let window = Duration::from_millis(moment.window_ms);
+            moment.event_types.iter().all(|required| {
+                recent.iter().any(|(at, seen)| seen == required && at.elapsed() <= window)
+            })
+        })?;
+        recent.retain(|(_, seen)| !moment.event_types.contains(seen));
+        Some(moment.clone())
+    }
+
+    /// The last `count` handled events, newest first.
+    fn latest_event_records(&self, count: usize) -> Vec<EventRecord> {
+        self.event_history.lock().iter().rev().take(count).cloned().collect()
+    }
+
+    /// Recent event history as CSV, oldest first.
+    fn event_history_csv(&self) -> String {
+        let mut csv = String::from(EventRecord::CSV_HEADER);
+        csv.push('\n');
+        for record in self.event_history.lock().iter() {
+            csv.push_str(&record.to_csv_row());
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Runs `handler`, preceded by `first_event_effect` if this is the session's first handled event.
+    async fn with_first_event(&self, handler: impl Future<Output = Result<(), AppError>>) -> Result<(), AppError> {
+        let config = self.config();
+        if let Some(effect) = &config.first_event_effect {
+            if !self.first_event_seen.swap(true, Ordering::SeqCst) {
+                info!("First event of the session, playing first_event_effect");
+                self.enqueue_effect(effect)?;
+                if config.first_event_mode == FirstEventMode::Instead {
+                    return Ok(());
+                }
+            }
+        }
+        handler.await
+    }
+
+    async fn handle_unknown_platform(&self, event: &StreamlabsEvent) -> Result<(), AppError> {
+        let config = self.config();
+        match config.unknown_platform_behavior {
+            UnknownPlatformBehavior::Ignore => {
+                debug!("Ignoring event from unknown platform: type={}, for={:?}",
+                       event.event_type, event.event_for);
+                Ok(())
+            }
+            UnknownPlatformBehavior::Warn => {
+                warn!("Event from unconfigured platform: type={}, for={:?}",
+                      event.event_type, event.event_for);
+                Ok(())
+            }
+            UnknownPlatformBehavior::DefaultEffect => {
+                info!("Applying default effect for unknown platform: type={}, for={:?}",
+                      event.event_type, event.event_for);
+                match &config.unknown_platform_effect {
+                    Some(effect) => self.enqueue_effect(effect),
+                    None => {
+                        warn!("unknown_platform_behavior is default_effect but no unknown_platform_effect is configured");
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_donation(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        if let Some(amount_str) = message.amount.as_ref().or(message.formatted_amount.as_ref()) {
+            let donation = &events.donation;
+            let donated = donation_amount(message)?;
+            record_amount(donated);
+            let amount = donation.normalize(donated, donation_currency(message).as_deref());
+            // Small donations still count towards the goals below
+            if donation.min_amount.is_some_and(|min| amount < min) {
+                debug!("Donation of {} from {} is below min_amount, not playing an effect", amount_str, message.name);
+            } else if let Some(effect) = self.donation_effect(donation, message, amount) {
+                info!("Processing donation of {} from {}", amount_str, message.name);
+                self.enqueue_effect(&effect)?;
+            } else {
+                warn!("donation is enabled but has no tiers");
+            }
+
+            if let Some(milestone) = self.add_to_monthly_total(amount) {
+                self.enqueue_effect(&milestone)?;
+            }
+            if let Some(milestone) = self.add_to_session_total(amount) {
+                self.enqueue_effect(&milestone)?;
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// The donation's tier effect, recolored for its currency or the color named in its message.
+    fn donation_effect(&self, donation: &DonationEffect, message: &EventMessage, amount: f64) -> Option<LightEffect> {
+        let mut effect = self.select_tier(&donation.tiers, amount)?.clone();
+
+        if let Some(currency) = donation_currency(message) {
+            let themed = donation.currency_colors
+                .iter()
+                .find(|(code, _)| code.eq_ignore_ascii_case(&currency))
+                .map(|(_, color)| color);
+            if let Some(color) = themed {
+                info!("Using {} color {} for donation", currency, color);
+                effect.color = color.clone();
+            }
+        }
+        // Only names from the color table are taken, so arbitrary text can't reach the bridge
+        let requested = message.message.as_deref()
+            .filter(|_| donation.message_color_override)
+            .and_then(colors::find_named_color);
+        if let Some(color) = requested {
+            info!("Using color {} from the donation message", color);
+            effect.color = color.to_string();
+        }
+        Some(effect)
+    }
+
+    /// Monetary value of an event for sampling; events without an amount are worth 0.
+    fn event_value(&self, event: &StreamlabsEvent) -> f64 {
+        let amount = event.message.first()
+            .and_then(|message| message.amount.as_deref())
+            .and_then(parse_amount)
+            .unwrap_or(0.0);
+        match event.event_type.as_str() {
+            "bits" => amount * self.config().session_goal.bits_to_dollars,
+            "supersticker" | "superchat" => amount / YOUTUBE_MICROS_PER_UNIT,
+            _ => amount,
+        }
+    }
+
+    /// Index of the pooled event the rate cap keeps.
+    fn pick_sample(&self, events: &[StreamlabsEvent]) -> usize {
+        match self.config().rate_cap.sampling {
+            SamplingPolicy::Random => self.rng.lock().gen_range(0..events.len()),
+            SamplingPolicy::HighestValue => events
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| self.event_value(a).total_cmp(&self.event_value(b)))
+                .map_or(0, |(i, _)| i),
+        }
+    }
+
+    /// Adds a dollar amount to the combined session total, returning the highest milestone it crossed.
+    fn add_to_session_total(&self, amount: f64) -> Option<LightEffect> {
+        let config = self.config();
+        let goal = &config.session_goal;
+        if !goal.enabled {
+            return None;
+        }
+
+        let mut total = self.session_total.lock();
+        let before = *total;
+        *total += amount;
+        info!("Session support total is now {:.2}", *total);
+
+        goal.milestones
+            .iter()
+            .filter(|milestone| before < milestone.amount && *total >= milestone.amount)
+            .max_by(|a, b| a.amount.total_cmp(&b.amount))
+            .map(|milestone| {
+                info!("Session goal milestone {} reached", milestone.amount);
+                milestone.effect.clone()
+            })
+    }
+
+    /// Adds a donation to this month's total, returning the highest milestone it crossed.
+    fn add_to_monthly_total(&self, amount: f64) -> Option<LightEffect> {
+        let config = self.config();
+        let goal = &config.monthly_goal;
+        if !goal.enabled {
+            return None;
+        }
+
+        let month = Local::now().format("%Y-%m").to_string();
+        let mut monthly = self.monthly_total.lock();
+        if monthly.month != month {
+            info!("New month {}, resetting monthly donation total (was {} for {:?})",
+                  month, monthly.total, monthly.month);
+            *monthly = MonthlyTotal { month, total: 0.0 };
+        }
+
+        let before = monthly.total;
+        monthly.total += amount;
+        info!("Monthly donation total is now {}", monthly.total);
+        if let Err(e) = monthly.save(&goal.state_file) {
+            error!("Failed to save monthly goal state to {}: {}", goal.state_file, e);
+        }
+
+        goal.milestones
+            .iter()
+            .filter(|milestone| before < milestone.amount && monthly.total >= milestone.amount)
+            .max_by(|a, b| a.amount.total_cmp(&b.amount))
+            .map(|milestone| {
+                info!("Monthly goal milestone {} reached", milestone.amount);
+                milestone.effect.clone()
+            })
+    }
+
+    /// The highest tier `amount` reaches, or the lowest tier when it reaches none, whatever
+    /// order the tiers are listed in. `None` when there are no tiers.
+    fn select_tier<'a>(&self, tiers: &'a [TierEffect], amount: f64) -> Option<&'a LightEffect> {
+        let index = tier_index(tiers, amount)?;
+
+        let shift = self.adaptive_tier_shift();
+        if shift == 0 {
+            let _ = HANDLED_EFFECT.try_with(|handled| handled.borrow_mut().tier = Some(tiers[index].amount));
+            return Some(&tiers[index].effect);
+        }
+
+        // Shift along the tiers ordered by amount, not by their position in the config
+        let mut by_amount: Vec<usize> = (0..tiers.len()).collect();
+        by_amount.sort_by(|&a, &b| tiers[a].amount.total_cmp(&tiers[b].amount));
+        let rank = by_amount.iter().position(|&i| i == index).unwrap_or(0) as isize;
+        let shifted = (rank + shift).clamp(0, tiers.len() as isize - 1) as usize;
+
+        info!("Adaptive tiers shifted selection by {} (tier {} -> {})",
+              shift, tiers[index].amount, tiers[by_amount[shifted]].amount);
+        let _ = HANDLED_EFFECT.try_with(|handled| handled.borrow_mut().tier = Some(tiers[by_amount[shifted]].amount));
+        Some(&tiers[by_amount[shifted]].effect)
+    }
+
+    /// Number of tiers to move based on how many events arrived within the window.
+    fn adaptive_tier_shift(&self) -> isize {
+        let config = self.config();
+        let adaptive = &config.adaptive_tiers;
+        if !adaptive.enabled {
+            return 0;
+        }
+
+        let window = Duration::from_millis(adaptive.window_ms);
+        let mut recent = self.recent_events.lock();
+        while recent.front().is_some_and(|t| t.elapsed() > window) {
+            recent.pop_front();
+        }
+
+        let count = recent.len();
+        debug!("{} events in the last {} ms", count, adaptive.window_ms);
+        if count <= adaptive.quiet_max_events {
+            adaptive.steps as isize
+        } else if count >= adaptive.busy_min_events {
+            -(adaptive.steps as isize)
+        } else {
+            0
+        }
+    }
+
+    async fn handle_twitch_follow(&self, events: &EventConfig) -> Result<(), AppError> {
+        info!("Processing Twitch follow");
+        self.enqueue_effect(&events.twitch_follow.effect)
+    }
+
+    async fn handle_twitch_subscription(&self, event: &StreamlabsEvent, events: &EventConfig) -> Result<(), AppError> {
+        let message = event.message.first();
+        let months = message.and_then(|m| m.months).unwrap_or(1);
+        let is_gift = message.is_some_and(|m| {
+            m.gifter.is_some() || m.sub_type.as_deref().is_some_and(|sub_type| sub_type.contains("gift"))
+        });
+
+        if is_gift && events.twitch_gift_subscription.enabled {
+            return self.handle_gift_subscription(message, events).await;
+        }
+
+        let subscription = &events.twitch_subscription;
+        if !subscription.enabled {
+            info!("Ignoring Twitch subscription, only gift subscriptions are enabled");
+            return Ok(());
+        }
+        let Some(effect) = self.select_tier(&subscription.tiers, months as f64) else {
+            warn!("twitch_subscription is enabled but has no tiers");
+            return Ok(());
+        };
+        info!("Processing Twitch subscription ({} months)", months);
+        self.enqueue_effect(effect)
+    }
+
+    /// Plays the gift tier for the number of subs gifted. A count that can't be read plays the
+    /// lowest tier rather than dropping the gift.
+    async fn handle_gift_subscription(&self, message: Option<&EventMessage>, events: &EventConfig) -> Result<(), AppError> {
+        let gifts = &events.twitch_gift_subscription;
+        let Some(lowest) = gifts.tiers.iter().min_by(|a, b| a.amount.total_cmp(&b.amount)) else {
+            warn!("twitch_gift_subscription is enabled but has no tiers");
+            return Ok(());
+        };
+
+        let gifter = message.and_then(|m| m.gifter.as_deref().or(Some(m.name.as_str())))
+            .filter(|name| !name.is_empty())
+            .unwrap_or("anonymous");
+        let count = message.and_then(|m| m.gift_count.or_else(|| m.amount.as_deref()?.trim().parse().ok()));
+        let effect = match count {
+            Some(count) => {
+                info!("Processing {} gifted Twitch subs from {}", count, gifter);
+                self.select_tier(&gifts.tiers, count as f64).unwrap_or(&lowest.effect).clone()
+            }
+            None => {
+                info!("Processing gifted Twitch subs from {} (count unknown, using the lowest tier)", gifter);
+                lowest.effect.clone()
+            }
+        };
+        self.enqueue_effect(&effect)
+    }
+
+    async fn handle_twitch_redemption(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        let Some(reward) = &message.payload else {
+            warn!("Channel point redemption without a reward, ignoring");
+            return Ok(());
+        };
+
+        match events.twitch_redemptions.rewards.iter().find(|redemption| redemption.matches(reward)) {
+            Some(redemption) => {
+                info!("Processing {} redeeming {:?}", message.name, reward.name);
+                self.enqueue_effect(&redemption.effect)
+            }
+            None => {
+                info!("No effect configured for channel point reward {:?} ({:?})", reward.name, reward.id);
+                Ok(())
+            }
+        }
+    }
+
+    async fn handle_loyalty_redemption(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        let Some(reward) = message.product.as_deref() else {
+            warn!("Loyalty redemption without a reward title, ignoring");
+            return Ok(());
+        };
+
+        match events.streamlabs_loyalty.rewards.get(reward) {
+            Some(effect) => {
+                info!("Processing loyalty redemption of {:?}", reward);
+                self.enqueue_effect(effect)
+            }
+            None => {
+                info!("No effect configured for loyalty reward {:?}", reward);
+                Ok(())
+            }
+        }
+    }
+
+    async fn handle_super_sticker(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        self.handle_youtube_paid(message, &events.youtube_super_sticker, "youtube_super_sticker", "Super Sticker")
+    }
+
+    async fn handle_superchat(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        self.handle_youtube_paid(message, &events.youtube_superchat, "youtube_superchat", "Super Chat")
+    }
+
+    /// Picks the tier for a YouTube paid message, whose amount arrives in millionths.
+    fn handle_youtube_paid(&self, message: &EventMessage, mapping: &EventTieredEffect, key: &str, label: &str) -> Result<(), AppError> {
+        if let Some(amount_str) = &message.amount {
+            let micros = parse_amount(amount_str)
+                .ok_or_else(|| AppError::InvalidAmount(amount_str.clone()))?;
+            let amount = micros / YOUTUBE_MICROS_PER_UNIT;
+            record_amount(amount);
+            let Some(effect) = self.select_tier(&mapping.tiers, amount).cloned() else {
+                warn!("{} is enabled but has no tiers", key);
+                return Ok(());
+            };
+
+            let currency = donation_currency(message).unwrap_or_default();
+            info!("Processing {} worth {} {} from {}", label, amount, currency, message.name);
+            self.enqueue_effect(&effect)?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_raid(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        let raid = &events.twitch_raid;
+        let raiders = message.raiders.or(message.viewers).unwrap_or(0);
+        let Some(effect) = self.select_tier(&raid.tiers, raiders as f64) else {
+            warn!("twitch_raid is enabled but has no tiers");
+            return Ok(());
+        };
+        info!("Processing raid from {} with {} raiders", message.name, raiders);
+        self.enqueue_effect(effect)
+    }
+
+    async fn handle_host(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        info!("Processing host from {} with {} viewers", message.name, message.viewers.unwrap_or(0));
+        self.enqueue_effect(&events.twitch_host.effect)
+    }
+
+    async fn handle_youtube_membership(&self, events: &EventConfig) -> Result<(), AppError> {
+        info!("Processing YouTube membership");
+        self.enqueue_effect(&events.youtube_membership.effect)
+    }
+
+    async fn handle_bits(&self, message: &EventMessage, events: &EventConfig) -> Result<(), AppError> {
+        let config = self.config();
+        if let Some(amount_str) = &message.amount {
+            let amount = parse_amount(amount_str)
+                .ok_or_else(|| AppError::InvalidAmount(amount_str.clone()))?;
+            record_amount(amount);
+
+            let bits = &events.twitch_bits;
+            let base = self.select_tier(&bits.tiers, amount).cloned()
+                .or_else(|| bits.continuous.as_ref().and_then(ContinuousMapping::base_effect));
+            if bits.min_amount.is_some_and(|min| amount < min) {
+                debug!("{} bits from {} is below min_amount, not playing an effect", amount_str, message.name);
+            } else if let Some(mut effect) = base {
+                if let Some(mapping) = &bits.continuous {
+                    mapping.apply(&mut effect, amount);
+                    debug!("Mapped {} bits to brightness {} for {} ms", amount, effect.brightness, effect.duration);
+                }
+
+                info!("Processing {} bits from {}", amount_str, message.name);
+                self.enqueue_effect(&effect)?;
+            } else {
+                warn!("twitch_bits is enabled but has no tiers");
+            }
+
+            let dollars = amount * config.session_goal.bits_to_dollars;
+            if let Some(milestone) = self.add_to_session_total(dollars) {
+                self.enqueue_effect(&milestone)?;
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// The effect's color blended toward `theme_color` by its `theme_blend`.
+    fn themed_color(&self, effect: &LightEffect) -> String {
+        let config = self.config();
+        let (Some(theme), Some(blend)) = (&config.theme_color, effect.theme_blend) else {
+            return effect.color.clone();
+        };
+        match blend_colors(&effect.color, theme, blend) {
+            Ok(color) => {
+                debug!("Blended {} toward theme {} by {} -> {}", effect.color, theme, blend, color);
+                color
+            }
+            Err(e) => {
+                warn!("Could not blend {} toward theme {}: {}", effect.color, theme, e);
+                effect.color.clone()
+            }
+        }
+    }
+
+    /// Parses an effect color, substituting `fallback_color` so a bad edit never drops a reaction.
+    fn effect_color(&self, color: &str) -> Result<(u16, u8), AppError> {
+        self.parse_with_fallback(color, parse_color)
+    }
+
+    fn parse_with_fallback<T>(&self, color: &str, parse: fn(&str) -> Result<T, AppError>) -> Result<T, AppError> {
+        match parse(color) {
+            Ok(parsed) => Ok(parsed),
+            Err(e) => match &self.config().fallback_color {
+                Some(fallback) => {
+                    warn!("Invalid effect color {:?} ({}), using fallback color {}", color, e, fallback);
+                    parse(fallback)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Swaps a lit command's color for the effect's white color temperature, if it has one.
+    fn apply_color_temp(&self, effect: &LightEffect, command: &mut CommandLight) -> Result<(), AppError> {
+        let Some(mirek) = effect.color_temp_mirek else {
+            return Ok(());
+        };
+        check_color_temp(mirek)?;
+        if !effect.color.is_empty() {
+            warn!("Effect sets both color {:?} and color_temp_mirek {}, using the color temperature", effect.color, mirek);
+        }
+        if command.on == Some(true) {
+            command.hue = None;
+            command.sat = None;
+            command.xy = None;
+            command.ct = Some(mirek);
+        }
+        Ok(())
+    }
+
+    /// Swaps a lit command's hue/saturation for CIE xy when the effect uses `color_mode: "xy"`.
+    fn apply_color_mode(&self, effect: &LightEffect, color: &str, command: &mut CommandLight) -> Result<(), AppError> {
+        if effect.color_mode != ColorMode::Xy || command.on != Some(true) {
+            return Ok(());
+        }
+        let [x, y] = self.parse_with_fallback(color, parse_color_xy)?;
+        command.hue = None;
+        command.sat = None;
+        command.xy = Some((x, y));
+        Ok(())
+    }
+
+    /// Maps a failed bridge command to `AppError::Bridge`, counting it towards rediscovery.
+    fn bridge_error(&self, e: ControllerError) -> AppError {
+        self.bridge_failures.fetch_add(1, Ordering::Relaxed);
+        self.metrics.bridge_error();
+        AppError::Bridge(e.to_string())
+    }
+
+    /// Milliseconds to wait before retrying a failed command of `kind`, or `None` to give up.
+    fn retry_delay(&self, kind: CommandKind, attempt: u32, error: &hueclient::HueError) -> Option<u64> {
+        let config = self.config();
+        let policy = match kind {
+            CommandKind::Effect => &config.command_retry.effect,
+            CommandKind::Reset => &config.command_retry.reset,
+        };
+        let retryable = policy.retry_all_errors || is_rate_limited(error);
+        if !policy.enabled || !retryable || attempt >= policy.max_retries {
+            return None;
+        }
+        let delay = policy.backoff_ms
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+            .min(COMMAND_RETRY_MAX_MS);
+        warn!("{:?} command failed ({}), retrying in {} ms (attempt {}/{})",
+              kind, error, delay, attempt + 1, policy.max_retries);
+        Some(delay)
+    }
+
+    /// Sends a light command, retrying according to the policy for its kind. The bridge is
+    /// only locked for each attempt, not across the retry delay.
+    async fn send_light_command(&self, id: usize, command: &CommandLight, kind: CommandKind) -> Result<(), AppError> {
+        if self.config().dry_run {
+            info!("Dry run: light {} <- {:?}", id, command);
+            return Ok(());
+        }
+        self.send_with_retry(kind, |controller| controller.set_light(id, command)).await
+    }
+
+    /// `send_light_command` for a whole bridge group, in a single request.
+    async fn send_group_command(&self, id: usize, command: &CommandLight, kind: CommandKind) -> Result<(), AppError> {
+        if self.config().dry_run {
+            info!("Dry run: group {} <- {:?}", id, command);
+            return Ok(());
+        }
+        self.send_with_retry(kind, |controller| controller.set_group(id, command)).await
+    }
+
+    async fn send_with_retry(
+        &self,
+        kind: CommandKind,
+        attempt_command: impl Fn(&dyn LightController) -> Result<(), ControllerError>,
+    ) -> Result<(), AppError> {
+        let mut attempt = 0;
+        loop {
+            let result = attempt_command(&**self.controller.lock());
+            let error = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            let Some(delay) = self.retry_delay(kind, attempt, &error) else {
+                return Err(self.bridge_error(error));
+            };
+            attempt += 1;
+            sleep(Duration::from_millis(delay)).await;
+        }
+    }
+
+    /// Blocking counterpart of `send_light_command` for the watchdog and console resets. Like
+    /// it, the bridge is only locked for each attempt.
+    fn send_light_command_blocking(&self, id: usize, command: &CommandLight, kind: CommandKind) -> Result<(), AppError> {
+        if self.config().dry_run {
+            info!("Dry run: light {} <- {:?}", id, command);
+            return Ok(());
+        }
+        let mut attempt = 0;
+        loop {
+            let result = self.controller.lock().set_light(id, command);
+            let error = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            let Some(delay) = self.retry_delay(kind, attempt, &error) else {
+                return Err(self.bridge_error(error));
+            };
+            attempt += 1;
+            std::thread::sleep(Duration::from_millis(delay));
+        }
+    }
+
+    /// Starts `builtin` on every light when the backend can play it by itself. `false` (also for
+    /// dry runs) means none were started and the effect should be played command by command.
+    fn play_builtin(&self, lights: &[IdentifiedLight], builtin: Builtin) -> Result<bool, AppError> {
+        if self.config().dry_run {
+            return Ok(false);
+        }
+        for (index, light) in lights.iter().enumerate() {
+            let played = self.controller.lock().play_builtin(light.id, builtin).map_err(|e| self.bridge_error(e))?;
+            if !played {
+                if index > 0 {
+                    warn!("Light {} can't play a built-in {:?}, the others play it anyway", light.id, builtin);
+                }
+                return Ok(index > 0);
+            }
+            debug!("Light {} plays a built-in {:?}", light.id, builtin);
+        }
+        Ok(!lights.is_empty())
+    }
+
+    /// Replaces the bridge with a freshly discovered one using the same username.
+    fn rediscover_bridge(&self) -> Result<(), AppError> {
+        let config = self.config();
+        match config.backend {
+            Backend::Hue if config.credentials.hue.remote.enabled => {
+                warn!("Hue Remote API commands keep failing, reconnecting...");
+                let remote = hue_remote::HueRemoteController::connect(&config.credentials.hue.remote, &config.credentials.hue.username)?;
+                *self.controller.lock() = Box::new(remote);
+                self.bridge_failures.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+            Backend::Hue => {}
+            Backend::Lifx => {
+                warn!("LIFX commands keep failing, re-running LIFX discovery...");
+                *self.controller.lock() = Box::new(lifx::LifxController::discover(&config.lifx)?);
+                self.bridge_failures.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+            Backend::Wled => {
+                warn!("WLED commands keep failing, reconnecting to the WLED devices...");
+                *self.controller.lock() = Box::new(wled::WledController::connect(&config.wled)?);
+                self.bridge_failures.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+        warn!("Bridge commands keep failing, re-running bridge discovery...");
+        let bridge = Bridge::discover()
+            .ok_or_else(|| AppError::Bridge("Bridge rediscovery found no bridge".to_string()))?
+            .with_user(&config.credentials.hue.username);
+        let lights = bridge.get_all_lights()
+            .map_err(|e| AppError::Bridge(format!("Rediscovered bridge is not responding: {}", e)))?;
+
+        info!("Reconnected to rediscovered bridge at {} with {} lights", bridge.ip, lights.len());
+        *self.controller.lock() = Box::new(bridge);
+        self.bridge_failures.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The bridge's lights. A dry run has no bridge, so it makes up a light in the default state
+    /// for each id in `light_groups` and effect `target_lights`, or just light 1 when none are listed.
+    fn get_lights(&self, controller: &dyn LightController) -> Result<Vec<IdentifiedLight>, AppError> {
+        let config = self.config();
+        if !config.dry_run {
+            return controller.get_lights().map_err(|e| self.bridge_error(e));
+        }
+        let mut ids: Vec<usize> = config.light_groups.values().flatten().copied()
+            .chain(config.all_effects().into_iter().flat_map(|effect| effect.target_lights.iter().flatten().copied()))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.is_empty() {
+            ids.push(1);
+        }
+        let state = &config.default_state;
+        Ok(ids.into_iter().map(|id| IdentifiedLight {
+            id,
+            light: hueclient::Light {
+                name: format!("Dry run light {}", id),
+                modelid: String::new(),
+                swversion: String::new(),
+                uniqueid: String::new(),
+                state: hueclient::LightState {
+                    on: state.on,
+                    bri: Some(state.brightness),
+                    hue: Some(state.hue),
+                    sat: Some(state.saturation),
+                    ct: None,
+                    xy: None,
+                },
+            },
+        }).collect())
+    }
+
+    /// Narrows the bridge's lights down to the ones the effect targets; ids the bridge no longer
+    /// has are skipped.
+    fn resolve_targets(&self, effect: &LightEffect, lights: Vec<IdentifiedLight>) -> Vec<IdentifiedLight> {
+        self.refresh_known_lights(&lights);
+        let lights = match &effect.target_lights {
+            Some(ids) => {
+                for id in ids.iter().filter(|id| !lights.iter().any(|light| light.id == **id)) {
+                    warn!("Effect targets unknown light {}, skipping it", id);
+                }
+                lights.into_iter().filter(|light| ids.contains(&light.id)).collect()
+            }
+            None => lights,
+        };
+        let Some(name) = &effect.light_group else {
+            return lights;
+        };
+
+        match self.config().light_groups.get(name) {
+            Some(ids) => {
+                let targets: Vec<IdentifiedLight> = lights.into_iter().filter(|light| ids.contains(&light.id)).collect();
+                if targets.len() < ids.len() {
+                    debug!("Light group {:?} has {} of its {} lights on the bridge", name, targets.len(), ids.len());
+                }
+                targets
+            }
+            None => {
+                warn!("Unknown light group {:?}, applying effect to all lights", name);
+                lights
+            }
+        }
+    }
+
+    /// Records the bridge's current lights, warning once about each light that disappeared.
+    fn refresh_known_lights(&self, lights: &[IdentifiedLight]) {
+        let live: HashSet<usize> = lights.iter().map(|light| light.id).collect();
+        let mut known = self.known_lights.lock();
+        for id in removed_lights(&known, &live) {
+            warn!("Light {} is no longer on the bridge, effects will skip it", id);
+        }
+        *known = live;
+    }
+
+    /// Re-lists the bridge's lights after a failed command; true if `id` is gone.
+    fn light_removed(&self, id: usize) -> bool {
+        let lights = self.get_lights(&**self.controller.lock());
+        match lights {
+            Ok(lights) => {
+                self.refresh_known_lights(&lights);
+                !lights.iter().any(|light| light.id == id)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Looks up `target_group` by name or id among the bridge's groups, returning its id and lights.
+    fn resolve_hue_group(&self, name: &str) -> Result<Option<(usize, HashSet<usize>)>, AppError> {
+        if self.config().dry_run {
+            info!("Dry run: bridge group {:?} can't be looked up, applying effect to all lights", name);
+            return Ok(None);
+        }
+        let groups = self.controller.lock().get_groups()
+            .map_err(|e| self.bridge_error(e))?;
+        let Some(group) = groups.into_iter().find(|group| group_matches(group, name)) else {
+            warn!("Unknown bridge group {:?}, applying effect to all lights", name);
+            return Ok(None);
+        };
+        let lights = group.group.lights.iter().filter_map(|id| id.parse().ok()).collect();
+        Ok(Some((group.id, lights)))
+    }
+
+    /// Warns about light groups listing ids the bridge doesn't have, and effects naming missing groups.
+    fn validate_light_groups(&self) -> Result<(), AppError> {
+        let config = self.config();
+        let lights = self.get_lights(&**self.controller.lock())?;
+        self.refresh_known_lights(&lights);
+
+        for (name, ids) in &config.light_groups {
+            for id in ids {
+                if !lights.iter().any(|light| light.id == *id) {
+                    warn!("Light group {:?} references unknown light {}", name, id);
+                }
+            }
+        }
+
+        let groups = if config.dry_run {
+            Vec::new()
+        } else {
+            self.controller.lock().get_groups().map_err(|e| self.bridge_error(e))?
+        };
+        for effect in config.all_effects() {
+            if let Some(name) = &effect.light_group {
+                if !config.light_groups.contains_key(name) {
+                    warn!("Effect references unknown light group {:?}", name);
+                }
+            }
+            for id in effect.target_lights.iter().flatten() {
+                if !lights.iter().any(|light| light.id == *id) {
+                    warn!("Effect targets unknown light {}", id);
+                }
+            }
+            if let Some(name) = effect.target_group.as_ref().filter(|_| !config.dry_run) {
+                if !groups.iter().any(|group| group_matches(group, name)) {
+                    warn!("Effect targets unknown bridge group {:?}", name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Plays each scheduled effect at its time; times already past are skipped.
+    async fn run_schedule(&self, schedule: Vec<ScheduledEffect>) {
+        let now = Local::now();
+        let mut upcoming: Vec<(DateTime<Local>, LightEffect)> = schedule
+            .into_iter()
+            .filter_map(|scheduled| match scheduled.time.and_local_timezone(Local).earliest() {
+                Some(at) if at > now => Some((at, scheduled.effect)),
+                Some(_) => {
+                    info!("Skipping scheduled effect at {}, the time has passed", scheduled.time);
+                    None
+                }
+                None => {
+                    warn!("Skipping scheduled effect at {}, no such local time", scheduled.time);
+                    None
+                }
+            })
+            .collect();
+        upcoming.sort_by_key(|(at, _)| *at);
+
+        for (at, effect) in upcoming {
+            let wait = (at - Local::now()).to_std().unwrap_or_default();
+            info!("Next scheduled effect at {} (in {} s)", at, wait.as_secs());
+            sleep(wait).await;
+            if let Err(e) = self.enqueue_effect(&effect) {
+                error!("Error queuing scheduled effect: {}", e);
+            }
+        }
+    }
+
+    /// Diagnostic flash for a message that failed to parse, at most once per `min_interval_ms`.
+    async fn flash_parse_error(&self) -> Result<(), AppError> {
+        let flash = self.config().flash_on_parse_error.clone();
+        if !flash.enabled {
+            return Ok(());
+        }
+
+        {
+            let mut last = self.last_parse_error_flash.lock();
+            if last.is_some_and(|t| t.elapsed() < Duration::from_millis(flash.min_interval_ms)) {
+                debug!("Skipping parse error flash, flashed too recently");
+                return Ok(());
+            }
+            *last = Some(Instant::now());
+        }
+
+        info!("Flashing lights for a Streamlabs message that failed to parse");
+        self.enqueue_effect(&flash.effect)
+    }
+
+    fn default_state_command(&self) -> CommandLight {
+        let default_state = &self.config().default_state;
+        CommandLight {
+            on: Some(default_state.on),
+            bri: Some(default_state.brightness),
+            hue: Some(default_state.hue),
+            sat: Some(default_state.saturation),
+            alert: Some(default_state.alert.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Puts every light back into the default state right away.
+    fn reset_all_lights(&self) -> Result<(), AppError> {
+        let lights = self.get_lights(&**self.controller.lock())?;
+        let reset_command = self.default_state_command();
+        for light in &lights {
+            self.send_light_command_blocking(light.id, &reset_command, CommandKind::Reset)?;
+        }
+        info!("Reset {} lights", lights.len());
+        Ok(())
+    }
+
+    /// Fades every light not already in the default state to it over `ramp_ms`.
+    fn ramp_to_default(&self, ramp_ms: u64) -> Result<(), AppError> {
+        let config = self.config();
+        let lights = self.get_lights(&**self.controller.lock())?;
+        let ramp_command = CommandLight {
+            transitiontime: Some(transition_time(ramp_ms)),
+            ..self.default_state_command()
+        };
+
+        let mut ramped = 0;
+        for light in &lights {
+            if in_default_state(&light.light.state, &config.default_state) {
+                continue;
+            }
+            debug!("Ramping light {} to the default state over {} ms", light.id, ramp_ms);
+            self.send_light_command_blocking(light.id, &ramp_command, CommandKind::Reset)?;
+            ramped += 1;
+        }
+        info!("Ramped {} of {} lights to the default state", ramped, lights.len());
+        Ok(())
+    }
+
+    /// Runs once per idle period: restores any light not in the default state.
+    fn check_idle_lights(&self) -> Result<(), AppError> {
+        let watchdog = self.config().idle_watchdog.clone();
+        {
+            let mut last_effect_end = self.last_effect_end.lock();
+            match *last_effect_end {
+                Some(end) if end.elapsed() >= Duration::from_millis(watchdog.idle_ms) => *last_effect_end = None,
+                _ => return Ok(()),
+            }
+        }
+
+        let config = self.config();
+        let lights = self.get_lights(&**self.controller.lock())?;
+        let reset_command = self.default_state_command();
+
+        for light in &lights {
+            if !in_default_state(&light.light.state, &config.default_state) {
+                warn!("Idle watchdog: light {} was left out of its default state, restoring it", light.id);
+                self.send_light_command_blocking(light.id, &reset_command, CommandKind::Reset)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits out the previous effect's cooldown and reserves `cooldown_ms` for this one.
+    async fn wait_for_cooldown(&self, cooldown_ms: u64) {
+        let start = {
+            let mut until = self.cooldown_until.lock();
+            let now = Instant::now();
+            let start = until.map_or(now, |until| until.max(now));
+            *until = Some(start + Duration::from_millis(cooldown_ms));
+            start
+        };
+
+        let wait = start.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+            info!("Waiting {} ms for the previous effect's cooldown", wait.as_millis());
+            sleep(wait).await;
+        }
+    }
+
+    /// Queues an effect behind any already waiting; it plays once `run_effect_queue` gets to it.
+    fn enqueue_effect(&self, effect: &LightEffect) -> Result<(), AppError> {
+        // Only set while handling an event; API previews are not recorded
+        let _ = HANDLED_EFFECT.try_with(|handled| handled.borrow_mut().effect = Some(effect.clone()));
+        self.effect_queue.push(effect.clone(), EFFECT_SOURCE.try_with(EffectSource::clone).ok())
+    }
+
+    /// Applies queued effects one at a time until the queue closes.
+    async fn run_effect_queue(&self) {
+        let Some(mut receiver) = self.effect_queue.take_receiver() else {
+            error!("Effect queue consumer is already running");
+            return;
+        };
+        info!("Effect queue started");
+        // Effects pulled off the channel early to be thinned out or reordered
+        let mut pending = VecDeque::new();
+        loop {
+            let queued = match pending.pop_front() {
+                Some(queued) => queued,
+                None => match receiver.recv().await {
+                    Some(queued) => queued,
+                    None => break,
+                },
+            };
+            if self.shutting_down.load(Ordering::SeqCst) {
+                break;
+            }
+            if queued.effect.flush_queue {
+                let mut flushed = pending.len();
+                pending.clear();
+                while receiver.try_recv().is_ok() {
+                    flushed += 1;
+                }
+                info!("Flushed {} pending effects for a flush_queue effect", flushed);
+            }
+
+            // Keep receiving while the effect plays so a higher-priority one can interrupt it
+            let priority = queued.effect.priority;
+            let cancel = CancellationToken::new();
+            let run = self.apply_interruptible(&queued.effect, &cancel);
+            tokio::pin!(run);
+            let result = loop {
+                tokio::select! {
+                    result = &mut run => break result,
+                    Some(next) = receiver.recv() => {
+                        if next.effect.priority > priority && !cancel.is_cancelled() {
+                            info!("Interrupting effect with priority {} for one with priority {}",
+                                  priority, next.effect.priority);
+                            *self.cooldown_until.lock() = None;
+                            cancel.cancel();
+                        }
+                        pending.push_back(next);
+                        // The playing effect takes one of the slots
+                        let config = self.config();
+                        if let Some(max) = config.max_active_effects {
+                            enforce_effect_cap(&mut pending, max.saturating_sub(1), config.overflow_policy);
+                        }
+                    }
+                }
+            };
+            if let Err(e) = result {
+                error!("Error applying queued effect: {}", e);
+            }
+
+            let config = self.config();
+            while let Ok(queued) = receiver.try_recv() {
+                pending.push_back(queued);
+            }
+            if config.drain_stale_on_resume {
+                drop_stale_effects(&mut pending);
+            }
+            if let Some(max) = config.max_active_effects {
+                enforce_effect_cap(&mut pending, max, config.overflow_policy);
+            }
+            if config.queue_ordering == QueueOrdering::Priority {
+                order_by_priority(&mut pending);
+            }
+            // Higher effect priorities go first; the sort is stable, so ties keep the order above
+            pending.make_contiguous().sort_by_key(|queued| std::cmp::Reverse(queued.effect.priority));
+            // The newest flushing effect goes first, so nothing it flushes plays ahead of it
+            if let Some(flush) = pending.iter().rposition(|queued| queued.effect.flush_queue).and_then(|index| pending.remove(index)) {
+                pending.push_front(flush);
+            }
+        }
+        info!("Effect queue stopped");
+    }
+
+    async fn apply_effect(&self, effect: &LightEffect) -> Result<(), AppError> {
+        self.apply_interruptible(effect, &CancellationToken::new()).await
+    }
+
+    /// Records a finished effect for the metrics and the dashboard.
+    fn finish_effect(&self, effect: &LightEffect, started: Instant, success: bool) {
+        self.metrics.effect_finished(started.elapsed(), success);
+        *self.last_effect.lock() = Some(AppliedEffect {
+            finished_at: Local::now(),
+            effect: effect.clone(),
+            success,
+        });
+    }
+
+    /// Like `apply_effect`, but cutting the effect short and resetting once `cancel` fires.
+    async fn apply_interruptible(&self, effect: &LightEffect, cancel: &CancellationToken) -> Result<(), AppError> {
+        if let Some(buffer) = self.config().effect_cooldown_buffer_ms {
+            self.wait_for_cooldown(effect.duration + effect.hold_after_ms.unwrap_or(0) + buffer).await;
+        }
+        *self.last_effect_end.lock() = None;
+        let started = Instant::now();
+        let result = self.run_effect(effect, cancel).await;
+        *self.last_effect_end.lock() = Some(Instant::now());
+        self.finish_effect(effect, started, result.is_ok());
+        if result.is_ok() {
+            self.bridge_failures.store(0, Ordering::Relaxed);
+            self.bridge_restored().await;
+            return result;
+        }
+
+        let rediscovery = self.config().bridge_rediscovery.clone();
+        let failures = self.bridge_failures.load(Ordering::Relaxed);
+        if failures > 0 {
+            self.bridge_lost.store(true, Ordering::SeqCst);
+        }
+        if !rediscovery.enabled || failures < rediscovery.failure_threshold {
+            return result;
+        }
+        if !self.reconnect_bridge(&rediscovery, cancel).await {
+            return result;
+        }
+        self.bridge_restored().await;
+        if cancel.is_cancelled() {
+            return result;
+        }
+
+        // Play the effect that failed on the new connection so the event isn't lost
+        info!("Replaying the failed effect on the reconnected bridge");
+        *self.last_effect_end.lock() = None;
+        let started = Instant::now();
+        let result = self.run_effect(effect, cancel).await;
+        *self.last_effect_end.lock() = Some(Instant::now());
+        self.finish_effect(effect, started, result.is_ok());
+        result
+    }
+
+    /// Re-runs bridge discovery up to `max_attempts` times. Returns whether a bridge was found.
+    async fn reconnect_bridge(&self, rediscovery: &BridgeRediscoveryConfig, cancel: &CancellationToken) -> bool {
+        for attempt in 0..rediscovery.max_attempts {
+            if attempt > 0 {
+                let delay = rediscovery.retry_delay(attempt);
+                warn!("Retrying bridge discovery in {} ms (attempt {}/{})", delay, attempt + 1, rediscovery.max_attempts);
+                if interruptible_sleep(Duration::from_millis(delay), cancel).await {
+                    return false;
+                }
+            }
+            match self.rediscover_bridge() {
+                Ok(()) => return true,
+                Err(e) => error!("Bridge rediscovery failed: {}", e),
+            }
+        }
+        error!("Giving up on the bridge after {} discovery attempts", rediscovery.max_attempts);
+        false
+    }
+
+    /// Plays `bridge_reconnect_effect` the first time the bridge responds after failing.
+    async fn bridge_restored(&self) {
+        if !self.bridge_lost.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        info!("Bridge connection restored");
+
+        let Some(effect) = self.config().bridge_reconnect_effect.clone() else {
+            return;
+        };
+        {
+            let mut last = self.last_reconnect_flash.lock();
+            if last.is_some_and(|t| t.elapsed() < Duration::from_millis(RECONNECT_FLASH_MIN_INTERVAL_MS)) {
+                debug!("Skipping bridge reconnect effect, one played recently");
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        // Best effort; a failure here only means the bridge dropped out again
+        if let Err(e) = self.run_effect(&effect, &CancellationToken::new()).await {
+            warn!("Bridge reconnect effect failed: {}", e);
+        }
+    }
+
+    /// Plays out an effect's duration (strobe, rainbow, sustained alert or a plain wait) and its
+    /// hold, once `command` has been sent to `lights`. The bridge is only locked per command.
+    async fn hold_effect(
+        &self,
+        effect: &LightEffect,
+        lights: &[IdentifiedLight],
+        command: &CommandLight,
+        sat: u8,
+        budget_scale: f64,
+        cancel: &CancellationToken,
+    ) -> Result<(), AppError> {
+        let config = self.config();
+        let weight = |id: usize| config.light_weights.get(&id).copied().unwrap_or(1.0);
+        let alert_command = CommandLight {
+            alert: Some(effect.alert.clone()),
+            ..Default::default()
+        };
+
+        info!("Waiting {} ms before resetting", effect.duration);
+        let builtin = match (&effect.strobe, &effect.rainbow) {
+            (Some(_), _) => Some(Builtin::Strobe),
+            (None, Some(_)) => Some(Builtin::Rainbow),
+            (None, None) => None,
+        };
+        let played_builtin = match builtin {
+            Some(builtin) if !cancel.is_cancelled() => self.play_builtin(lights, builtin)?,
+            _ => false,
+        };
+        if cancel.is_cancelled() {
+            // Interrupted before it finished lighting up; go straight to the reset
+        } else if played_builtin {
+            let total_ms = match builtin {
+                Some(Builtin::Rainbow) => effect.duration.min(MAX_RAINBOW_MS),
+                _ => effect.duration,
+            };
+            interruptible_sleep(Duration::from_millis(total_ms), cancel).await;
+        } else if let Some(strobe) = &effect.strobe {
+            let interval = Duration::from_millis(strobe.interval_ms.max(MIN_STROBE_INTERVAL_MS));
+            let lit_command = CommandLight {
+                alert: None,
+                transitiontime: Some(0),
+                ..command.clone()
+            };
+            let alternate_command = match &strobe.second_color {
+                Some(color) => {
+                    let (hue, sat) = self.effect_color(color)?;
+                    let mut alternate = CommandLight {
+                        hue: Some(hue),
+                        sat: Some(sat),
+                        xy: None,
+                        ct: None,
+                        ..lit_command.clone()
+                    };
+                    self.apply_color_mode(effect, color, &mut alternate)?;
+                    alternate
+                }
+                None => CommandLight {
+                    on: Some(false),
+                    transitiontime: Some(0),
+                    ..Default::default()
+                },
+            };
+
+            let duration = Duration::from_millis(effect.duration);
+            let started = Instant::now();
+            let mut lit = true;
+            loop {
+                let remaining = duration.saturating_sub(started.elapsed());
+                if remaining.is_zero() || self.shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+                if interruptible_sleep(remaining.min(interval), cancel).await || started.elapsed() >= duration {
+                    break;
+                }
+
+                lit = !lit;
+                let phase = if lit { &lit_command } else { &alternate_command };
+                for light in lights {
+                    let phase = scale_command(phase, weight(light.id) * budget_scale, weight(light.id));
+                    self.send_light_command(light.id, &phase, CommandKind::Effect).await?;
+                }
+            }
+        } else if let Some(rainbow) = &effect.rainbow {
+            let total_ms = effect.duration.min(MAX_RAINBOW_MS);
+            if total_ms < effect.duration {
+                warn!("Capping rainbow cycle at {} ms (duration {} ms)", MAX_RAINBOW_MS, effect.duration);
+            }
+            let steps = rainbow.steps.max(1);
+            let step_ms = total_ms / steps as u64;
+            for step in 0..steps {
+                if self.shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+                // Only the hue changes; brightness and saturation stay as the effect set them.
+                // An xy effect never set a saturation, so it's sent along with the hue
+                let hue_command = CommandLight {
+                    hue: Some((step as u64 * 65535 / steps as u64) as u16),
+                    sat: command.xy.map(|_| sat),
+                    transitiontime: Some(transition_time(step_ms)),
+                    ..Default::default()
+                };
+                for light in lights {
+                    self.send_light_command(light.id, &hue_command, CommandKind::Effect).await?;
+                }
+                if interruptible_sleep(Duration::from_millis(step_ms), cancel).await {
+                    break;
+                }
+            }
+        } else if effect.sustain_alert && effect.alert == "lselect" {
+            let mut remaining = effect.duration;
+            while remaining > LSELECT_CYCLE_MS {
+                if interruptible_sleep(Duration::from_millis(LSELECT_CYCLE_MS), cancel).await {
+                    remaining = 0;
+                    break;
+                }
+                remaining -= LSELECT_CYCLE_MS;
+                debug!("Re-triggering lselect, {} ms remaining", remaining);
+                for light in lights {
+                    self.send_light_command(light.id, &alert_command, CommandKind::Effect).await?;
+                }
+            }
+            interruptible_sleep(Duration::from_millis(remaining), cancel).await;
+        } else {
+            interruptible_sleep(Duration::from_millis(effect.duration), cancel).await;
+        }
+
+        if let Some(hold_ms) = effect.hold_after_ms.filter(|_| !cancel.is_cancelled()) {
+            info!("Holding color for {} ms before resetting", hold_ms);
+            let stop_alert = CommandLight {
+                alert: Some("none".to_string()),
+                ..Default::default()
+            };
+            for light in lights {
+                self.send_light_command(light.id, &stop_alert, CommandKind::Effect).await?;
+            }
+            interruptible_sleep(Duration::from_millis(hold_ms), cancel).await;
+        }
+        Ok(())
+    }
+
+    async fn run_effect(&self, effect: &LightEffect, cancel: &CancellationToken) -> Result<(), AppError> {
+        info!("Applying light effect: {:?}", effect);
+        let color = self.themed_color(effect);
+        // A color temperature replaces the color, which may then be left empty
+        let (hue, sat) = match effect.color_temp_mirek {
+            Some(_) => (0, 0),
+            None => self.effect_color(&color)?,
+        };
+        let zero_brightness = self.config().zero_brightness;
+        if effect.brightness == 0 && zero_brightness == ZeroBrightness::Reject {
+            return Err(AppError::Config("Effect brightness 0 is rejected by zero_brightness".to_string()));
+        }
+        
+        let mut command = effect_command(effect, hue, sat);
+        match effect.color_temp_mirek {
+            Some(_) => self.apply_color_temp(effect, &mut command)?,
+            None => self.apply_color_mode(effect, &color, &mut command)?,
+        }
+        let alert_command = CommandLight {
+            alert: Some(effect.alert.clone()),
+            ..Default::default()
+        };
+        if let Some(fade_in_ms) = effect.fade_in_ms {
+            // The alert is sent separately once the fade has settled
+            command.alert = None;
+            command.transitiontime = Some(transition_time(fade_in_ms));
+        }
+
+        match (command.ct, command.xy) {
+            (Some(ct), _) => info!("Created light command with ct={}", ct),
+            (None, Some((x, y))) => info!("Created light command with xy=({:.4}, {:.4})", x, y),
+            (None, None) => info!("Created light command with hue={}, sat={}", hue, sat),
+        }
+        
+        // Held only for the call, so other bridge users aren't stalled for the whole effect
+        let lights = self.get_lights(&**self.controller.lock())?;
+        let mut lights = self.resolve_targets(effect, lights);
+        let group = match &effect.target_group {
+            Some(name) => self.resolve_hue_group(name)?,
+            None => None,
+        };
+        if let Some((id, members)) = &group {
+            lights.retain(|light| members.contains(&light.id));
+            debug!("Bridge group {} has {} of the targeted lights", id, lights.len());
+        }
+
+        if let Some(count) = effect.random_light_count {
+            if count > lights.len() {
+                warn!("random_light_count is {} but only {} lights are targeted, using all of them", count, lights.len());
+            }
+            lights = lights.choose_multiple(&mut *self.rng.lock(), count).cloned().collect();
+            lights.sort_by_key(|light| light.id);
+            let ids: Vec<usize> = lights.iter().map(|light| light.id).collect();
+            info!("Randomly selected lights {:?}", ids);
+        }
+            
+        let config = self.config();
+        let weight = |id: usize| config.light_weights.get(&id).copied().unwrap_or(1.0);
+        let mut budget_scale = 1.0;
+        if let (Some(budget), Some(bri)) = (config.max_total_brightness, command.bri) {
+            let total: f64 = lights.iter().map(|light| (bri as f64 * weight(light.id)).min(254.0)).sum();
+            if total > budget as f64 {
+                budget_scale = budget as f64 / total;
+                info!("Total brightness {:.0} exceeds budget {}, scaling brightness by {:.2}",
+                      total, budget, budget_scale);
+            }
+        }
+
+        info!("Applying effect to {} lights", lights.len());
+        let power_on_fade_ms = config.power_on_fade_ms;
+        let sweep_step = Duration::from_millis(effect.sweep_step_ms.unwrap_or(DEFAULT_SWEEP_STEP_MS));
+        // When every light gets the same command, one group request (group 0 is every light)
+        // changes them all at once instead of staggering bulb by bulb
+        let uniform = effect.sweep.is_none()
+            && effect.random_light_count.is_none()
+            && effect.light_group.is_none()
+            && effect.target_lights.is_none()
+            && power_on_fade_ms.is_none()
+            && budget_scale == 1.0
+            && lights.iter().all(|light| weight(light.id) == 1.0)
+            // A dry run logs each light's command
+            && !config.dry_run;
+        let group_id = group.as_ref().map_or(0, |(id, _)| *id);
+        let mut grouped = false;
+        if uniform && !lights.is_empty() {
+            info!("Setting state for bridge group {}", group_id);
+            match self.send_group_command(group_id, &command, CommandKind::Effect).await {
+                Ok(()) => grouped = true,
+                Err(e) => warn!("Group command failed, falling back to per-light commands: {}", e),
+            }
+        }
+        let order = if grouped {
+            Vec::new()
+        } else {
+            sweep_order(effect.sweep, &config.mirror_pairs, &lights)
+        };
+        let mut removed = HashSet::new();
+        let mut previous_step = order.first().map_or(0, |&(step, _)| step);
+        for (step, light) in order {
+            if step != previous_step {
+                if interruptible_sleep(sweep_step, cancel).await {
+                    break;
+                }
+                previous_step = step;
+            }
+            info!("Setting state for light {}", light.id);
+            let command = scale_command(&command, weight(light.id) * budget_scale, weight(light.id));
+            let sent = match power_on_fade_ms {
+                Some(fade_ms) if !light.light.state.on && command.on == Some(true) => {
+                    debug!("Light {} is off, warming up over {} ms", light.id, fade_ms);
+                    let warm_up = CommandLight {
+                        on: Some(true),
+                        bri: Some(1),
+                        transitiontime: Some(0),
+                        ..Default::default()
+                    };
+                    let fade = transition_time(fade_ms).max(command.transitiontime.unwrap_or(0));
+                    let faded = CommandLight {
+                        transitiontime: Some(fade),
+                        ..command.clone()
+                    };
+                    match self.send_light_command(light.id, &warm_up, CommandKind::Effect).await {
+                        Ok(()) => self.send_light_command(light.id, &faded, CommandKind::Effect).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                _ => self.send_light_command(light.id, &command, CommandKind::Effect).await,
+            };
+            if let Err(e) = sent {
+                if !self.light_removed(light.id) {
+                    return Err(e);
+                }
+                warn!("Skipping light {}, which was removed from the bridge: {}", light.id, e);
+                removed.insert(light.id);
+            }
+        }
+        lights.retain(|light| !removed.contains(&light.id));
+
+        if let Some(fade_in_ms) = effect.fade_in_ms.filter(|_| !cancel.is_cancelled()) {
+            info!("Fading in over {} ms before the alert", fade_in_ms);
+            if !interruptible_sleep(Duration::from_millis(fade_in_ms), cancel).await {
+                for light in &lights {
+                    self.send_light_command(light.id, &alert_command, CommandKind::Effect).await?;
+                }
+            }
+        }
+
+        self.hold_effect(effect, &lights, &command, sat, budget_scale, cancel).await?;
+        
+        if cancel.is_cancelled() {
+            info!("Effect interrupted by a higher-priority effect");
+        }
+        info!("Resetting lights to default state");
+        let reset_command = CommandLight {
+            transitiontime: effect.transition_ms.map(transition_time),
+            ..self.default_state_command()
+        };
+        let off_command = CommandLight {
+            on: Some(false),
+            ..reset_command.clone()
+        };
+
+        for light in &lights {
+            let previous = effect.restore_previous
+                .then(|| previous_state_command(&light.light.state))
+                .flatten();
+            if let Some(previous) = previous {
+                info!("Restoring light {} to its previous state", light.id);
+                let previous = CommandLight {
+                    transitiontime: reset_command.transitiontime,
+                    ..previous
+                };
+                self.send_light_command(light.id, &previous, CommandKind::Reset).await?;
+            } else if effect.restore_off && !light.light.state.on {
+                info!("Turning light {} back off", light.id);
+                self.send_light_command(light.id, &off_command, CommandKind::Reset).await?;
+            } else {
+                info!("Resetting light {}", light.id);
+                self.send_light_command(light.id, &reset_command, CommandKind::Reset).await?;
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+// Sliding one-minute window of handled events plus the overflow waiting to be sampled
+#[derive(Default)]
+struct EventSampler {
+    accepted: VecDeque<Instant>,
+    overflow: Vec<StreamlabsEvent>,
+}
+
+impl EventSampler {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    /// Passes the event through if the window has room, otherwise pools it.
+    fn admit(&mut self, event: StreamlabsEvent, cap: &RateCapConfig) -> Option<StreamlabsEvent> {
+        if !cap.enabled {
+            return Some(event);
+        }
+        while self.accepted.front().is_some_and(|t| t.elapsed() >= Self::WINDOW) {
+            self.accepted.pop_front();
+        }
+        if self.accepted.len() < cap.max_per_minute {
+            self.accepted.push_back(Instant::now());
+            return Some(event);
+        }
+        debug!("Rate cap of {} events per minute reached, pooling {} event", cap.max_per_minute, event.event_type);
+        self.overflow.push(event);
+        None
+    }
+
+    /// How long until the window frees a slot for the pooled events, if any are waiting.
+    fn next_slot(&self) -> Option<Duration> {
+        if self.overflow.is_empty() {
+            return None;
+        }
+        let oldest = self.accepted.front().map_or(Duration::ZERO, |t| t.elapsed());
+        Some(Self::WINDOW.saturating_sub(oldest))
+    }
+
+    /// Takes the pooled event chosen by `pick` and drops the rest.
+    fn take_sample(&mut self, pick: impl FnOnce(&[StreamlabsEvent]) -> usize) -> Option<StreamlabsEvent> {
+        if self.overflow.is_empty() {
+            return None;
+        }
+        let index = pick(&self.overflow);
+        let event = self.overflow.swap_remove(index);
+        if !self.overflow.is_empty() {
+            info!("Rate cap sampled out {} events, keeping a {} event", self.overflow.len(), event.event_type);
+            self.overflow.clear();
+        }
+        Some(event)
+    }
+}
+
+/// Sleeps for `duration`, returning `true` early if the effect was interrupted.
+async fn interruptible_sleep(duration: Duration, cancel: &CancellationToken) -> bool {
+    tokio::select! {
+        _ = sleep(duration) => false,
+        _ = cancel.cancelled() => true,
+    }
+}
+
+/// Keeps only the effects of the newest queued event of each type and platform, in arrival order.
+/// Effects that no event queued are always kept.
+fn drop_stale_effects(pending: &mut VecDeque<QueuedEffect>) {
+    let before = pending.len();
+    let mut newest_event = HashMap::new();
+    for source in pending.iter().filter_map(|queued| queued.source.as_ref()) {
+        newest_event.insert((source.event_type.clone(), source.event_for.clone()), source.event_id);
+    }
+    pending.retain(|queued| match &queued.source {
+        Some(source) => newest_event[&(source.event_type.clone(), source.event_for.clone())] == source.event_id,
+        None => true,
+    });
+
+    if pending.len() < before {
+        info!("Effects fell behind, skipping {} stale queued effects", before - pending.len());
+    }
+}
+
+/// The message published to MQTT and Discord for a handled event. The amount is the one its
+/// handler read, so bits with separators and YouTube micros come out in whole units.
+fn event_message(event: &StreamlabsEvent, handled: &HandledEffect, success: bool) -> serde_json::Value {
+    let message = event.message.first();
+    let amount = handled.amount.or_else(|| message.and_then(|m| m.amount.as_deref()).and_then(parse_amount));
+    serde_json::json!({
+        "timestamp": Local::now().to_rfc3339(),
+        "event_type": event.event_type,
+        "platform": event.event_for,
+        "name": message.map(|m| m.name.as_str()).filter(|name| !name.is_empty()),
+        "amount": amount,
+        "currency": message.and_then(donation_currency),
+        "tier": handled.tier,
+        "effect": handled.effect,
+        "success": success,
+    })
+}
+
+/// Drops queued effects per `policy` until no more than `capacity` wait, logging each one.
+fn enforce_effect_cap(pending: &mut VecDeque<QueuedEffect>, capacity: usize, policy: OverflowPolicy) {
+    let score = |queued: &QueuedEffect| queued.source.as_ref().map_or(0.0, |source| source.score);
+    while pending.len() > capacity {
+        let index = match policy {
+            OverflowPolicy::Oldest => pending.iter().enumerate().min_by_key(|(_, queued)| queued.sequence),
+            OverflowPolicy::Newest => pending.iter().enumerate().max_by_key(|(_, queued)| queued.sequence),
+            OverflowPolicy::LowestPriority => pending.iter().enumerate().min_by(|(_, a), (_, b)| {
+                a.effect.priority.cmp(&b.effect.priority)
+                    .then(score(a).total_cmp(&score(b)))
+                    .then(b.sequence.cmp(&a.sequence))
+            }),
+        }.map(|(index, _)| index);
+        let Some(dropped) = index.and_then(|index| pending.remove(index)) else {
+            break;
+        };
+        let event = dropped.source.as_ref().map_or("no event", |source| source.event_type.as_str());
+        warn!("Over max_active_effects, dropping {} queued effect (color {}, {})", policy.reason(), dropped.effect.color, event);
+    }
+}
+
+/// Sorts queued effects by their event's priority plus monetary value, highest first.
+/// Effects without an event and ties keep their arrival order.
+fn order_by_priority(pending: &mut VecDeque<QueuedEffect>) {
+    let score = |queued: &QueuedEffect| queued.source.as_ref().map_or(0.0, |source| source.score);
+    pending.make_contiguous().sort_by(|a, b| score(b).total_cmp(&score(a)));
+}
+
+fn process_event(message: &str, tx: &mpsc::Sender<StreamlabsEvent>, parse_errors: &mpsc::Sender<()>) {
+    info!("Processing message: {}", message);
+    match serde_json::from_str::<StreamlabsEvent>(message) {
+        Ok(event) => {
+            info!("Successfully parsed event: {:?}", event);
+            match (event.event_type.as_str(), event.event_for.as_deref()) {
+                ("donation", None) |
+                ("follow", Some("twitch_account")) |
+                ("subscription" | "resub" | "subMysteryGift", Some("twitch_account")) |
+                ("bits", Some("twitch_account")) |
+                ("raid" | "host" | "redemption", Some("twitch_account")) |
+                ("loyalty_store_redemption", Some("streamlabs")) |
+                ("supersticker" | "superchat" | "membership", Some("youtube_account")) => {
+                    info!("Sending valid event to handler: {:?}", event);
+                    if let Err(e) = tx.blocking_send(event) {
+                        error!("Failed to send event to handler: {}", e);
+                    }
+                }
+                (_, platform) if is_unknown_platform(platform) => {
+                    info!("Sending event from unknown platform to handler: {:?}", event);
+                    if let Err(e) = tx.blocking_send(event) {
+                        error!("Failed to send event to handler: {}", e);
                     }
                 }
                 _ => {
@@ -336,36 +4023,621 @@ fn process_event(message: &str, tx: &mpsc::Sender<StreamlabsEvent>) {
         Err(e) => {
             error!("Failed to parse Streamlabs event: {}", e);
             error!("Raw message that failed to parse: {}", message);
+            // Dropped when a flash is already pending
+            let _ = parse_errors.try_send(());
+        }
+    }
+}
+
+/// Loads the config from `path` as JSON, TOML or YAML depending on its extension.
+fn load_config(path: &str) -> Result<Config, AppError> {
+    info!("Loading configuration from {}...", path);
+    let mut raw = config_file::read(path)?;
+
+    if let Some(path) = raw.get("effects_file").and_then(|path| path.as_str()).map(str::to_string) {
+        info!("Loading effects library from {}", path);
+        let library: HashMap<String, LightEffect> = serde_json::from_value(config_file::read(&path)?)?;
+        resolve_effect_refs(&mut raw, &library)?;
+        info!("Resolved effect references against {} library effects", library.len());
+    }
+
+    let mut config: Config = serde_json::from_value(raw)?;
+    config.path = path.to_string();
+    let active_show = config.active_show.clone();
+    config.select_show(active_show.as_deref())?;
+    config.validate()?;
+    config.check_zero_brightness()?;
+
+    if let Some(path) = &config.secrets_file {
+        info!("Loading secrets from {}", path);
+        let secrets: Secrets = serde_json::from_value(config_file::read(path)?)?;
+        config.credentials.merge_secrets(secrets);
+    }
+
+    Ok(config)
+}
+
+/// Replaces every `"effect_ref": "<name>"` in the raw config with the named library effect.
+fn resolve_effect_refs(value: &mut serde_json::Value, library: &HashMap<String, LightEffect>) -> Result<(), AppError> {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(reference) = map.remove("effect_ref") {
+                let name = reference.as_str()
+                    .ok_or_else(|| AppError::Config(format!("effect_ref must be a string, got {}", reference)))?;
+                let effect = library.get(name)
+                    .ok_or_else(|| AppError::Config(format!("Unknown effect_ref {:?}", name)))?;
+                map.insert("effect".to_string(), serde_json::to_value(effect)?);
+            }
+            for child in map.values_mut() {
+                resolve_effect_refs(child, library)?;
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for child in items {
+                resolve_effect_refs(child, library)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Reloads the config whenever its file changes, for as long as the returned watcher lives. The
+/// directory is watched rather than the file so editors that save by replacing it are noticed.
+fn watch_config(state: Arc<AppState>) -> Result<notify::RecommendedWatcher, AppError> {
+    use notify::Watcher;
+
+    let path = Path::new(&state.config().path).to_path_buf();
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+    info!("Watching {} for changes", path.display());
+
+    std::thread::spawn(move || {
+        let changes_config = |event: notify::Result<notify::Event>| match event {
+            Ok(event) => !event.kind.is_access()
+                && event.paths.iter().any(|changed| changed.file_name() == file_name.as_deref()),
+            Err(e) => {
+                warn!("Config watch error: {}", e);
+                false
+            }
+        };
+        // Ends once the watcher, and with it the sender, is dropped
+        while let Ok(event) = rx.recv() {
+            if !changes_config(event) {
+                continue;
+            }
+            while rx.recv_timeout(Duration::from_millis(CONFIG_RELOAD_DEBOUNCE_MS)).is_ok() {}
+            state.reload_config();
+        }
+    });
+    Ok(watcher)
+}
+
+fn reset_lights(state: &AppState) {
+    info!("Resetting all lights to the default state");
+    if let Err(e) = state.reset_all_lights() {
+        error!("Failed to reset lights: {}", e);
+    }
+}
+
+/// Opens the Streamlabs socket. The client's own reconnect is turned off; instead a dropped
+/// connection sends `Control::SocketLost` once, so the socket thread can retry with backoff.
+fn connect_streamlabs(
+    socket_url: &str,
+    event_tx: &mpsc::Sender<StreamlabsEvent>,
+    parse_error_tx: &mpsc::Sender<()>,
+    control_tx: &std::sync::mpsc::Sender<Control>,
+    connected: &Arc<AtomicBool>,
+) -> Result<Client, AppError> {
+    let lost = |reason: &'static str| {
+        let control_tx = control_tx.clone();
+        let connected = connected.clone();
+        move |payload: Payload, _: RawClient| {
+            // A dead socket keeps reporting errors, only the first one counts
+            if connected.swap(false, Ordering::SeqCst) {
+                warn!("Socket.IO {}: {:?}", reason, payload);
+                if let Err(e) = control_tx.send(Control::SocketLost) {
+                    error!("Error reporting lost Streamlabs connection: {}", e);
+                }
+            }
+        }
+    };
+
+    let client = ClientBuilder::new(socket_url)
+        .transport_type(rust_socketio::TransportType::Websocket)
+        .reconnect(false)
+        .on("open", |_, _| {
+            info!("Socket.IO connected successfully!");
+        })
+        .on("close", lost("closed"))
+        .on("disconnect", lost("disconnected"))
+        .on("connect_error", lost("connection error"))
+        .on("error", lost("error"))
+        .on("event", {
+            let tx = event_tx.clone();
+            let parse_errors = parse_error_tx.clone();
+            move |payload: Payload, _| {
+                info!("Received raw socket event");
+                match payload {
+                    #[allow(deprecated)]
+                    Payload::String(message) => {
+                        info!("Processing String payload: {}", message);
+                        process_event(&message, &tx, &parse_errors);
+                    }
+                    Payload::Text(json_value) => {
+                        info!("Processing Text payload: {:?}", json_value);
+                        if let Some(first_event) = json_value.first() {
+                            if let Ok(message) = serde_json::to_string(first_event) {
+                                process_event(&message, &tx, &parse_errors);
+                            } else {
+                                error!("Failed to serialize JSON value to string");
+                            }
+                        } else {
+                            error!("Text payload is not an array");
+                        }
+                    }
+                    other => {
+                        warn!("Received unexpected payload type: {:?}", other);
+                    }
+                }
+            }
+        })
+        .connect()?;
+    connected.store(true, Ordering::SeqCst);
+    Ok(client)
+}
+
+/// Calls `connect` until it succeeds, waiting 1s, 2s, 4s... (capped at a minute, plus up to
+/// 25% jitter) between attempts. Console resets received while waiting go to `on_reset`.
+/// Returns `None` if shutdown is requested first.
+fn connect_with_backoff(
+    connect: impl Fn() -> Result<Client, AppError>,
+    control_rx: &std::sync::mpsc::Receiver<Control>,
+    mut on_reset: impl FnMut(),
+) -> Option<Client> {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let mut attempt: u32 = 0;
+    loop {
+        match connect() {
+            Ok(client) => return Some(client),
+            Err(e) => error!("Failed to connect to Streamlabs: {}", e),
+        }
+        let delay = socket_retry_delay(attempt);
+        attempt = attempt.saturating_add(1);
+        warn!("Retrying Streamlabs connection in {:.1}s (attempt {})", delay.as_secs_f32(), attempt + 1);
+
+        let deadline = Instant::now() + delay;
+        loop {
+            match control_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Err(RecvTimeoutError::Timeout) => break,
+                Ok(Control::ResetLights) => on_reset(),
+                // Left over from the connection being replaced
+                Ok(Control::SocketLost) => {}
+                Ok(Control::Shutdown) | Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+fn socket_retry_delay(attempt: u32) -> Duration {
+    let base = SOCKET_RETRY_MIN_MS.saturating_mul(1 << attempt.min(16)).min(SOCKET_RETRY_MAX_MS);
+    Duration::from_millis(base + rand::thread_rng().gen_range(0..=base / 4))
+}
+
+/// Registers a new bridge user through link-button pairing, or remotely with `hue.remote`, and
+/// saves the username to the secrets file if one is configured, otherwise to `config.json`.
+fn pair_bridge(config: &mut Config) -> Result<(), AppError> {
+    let username = if config.credentials.hue.remote.enabled {
+        info!("Pairing with the bridge through the Hue Remote API...");
+        let username = hue_remote::HueRemoteController::register_user(&config.credentials.hue.remote, PAIRING_DEVICE_TYPE)?;
+        info!("Paired with the bridge through the Hue Remote API");
+        username
+    } else {
+        pair_local_bridge(config)?
+    };
+
+    let path = config.secrets_file.as_deref().unwrap_or(&config.path);
+    let mut file: serde_json::Value = match &config.secrets_file {
+        Some(path) if !std::path::Path::new(path).exists() => serde_json::json!({}),
+        _ => config_file::read(path)?,
+    };
+    let hue = match &config.secrets_file {
+        Some(_) => &mut file["hue"],
+        None => &mut file["credentials"]["hue"],
+    };
+    hue["username"] = serde_json::Value::String(username.clone());
+    config_file::write(path, &file)?;
+    info!("Saved the new Hue username to {}", path);
+
+    config.credentials.hue.username = username;
+    Ok(())
+}
+
+/// Waits for the link button of the bridge on the LAN and registers a user with it.
+fn pair_local_bridge(config: &Config) -> Result<String, AppError> {
+    let bridge = match config.credentials.hue.bridge_ip.as_deref().map(str::parse::<std::net::IpAddr>) {
+        Some(Ok(ip)) => Bridge::for_ip(ip),
+        Some(Err(e)) => return Err(AppError::Config(format!("Invalid hue.bridge_ip: {}", e))),
+        None => Bridge::discover()
+            .ok_or_else(|| AppError::Bridge("Failed to discover a bridge to pair with".to_string()))?,
+    };
+
+    info!("Press the link button on the Hue bridge at {} within {} seconds...", bridge.ip, PAIRING_TIMEOUT_MS / 1000);
+    let deadline = Instant::now() + Duration::from_millis(PAIRING_TIMEOUT_MS);
+    let username = loop {
+        match bridge.clone().register_user(PAIRING_DEVICE_TYPE) {
+            Ok(paired) => break paired.username,
+            // 101: link button not pressed
+            Err(hueclient::HueError::BridgeError { code: 101, .. }) if Instant::now() < deadline => {
+                debug!("Link button not pressed yet, waiting...");
+                std::thread::sleep(Duration::from_millis(PAIRING_POLL_MS));
+            }
+            Err(hueclient::HueError::BridgeError { code: 101, .. }) => {
+                return Err(AppError::Bridge(format!(
+                    "The bridge's link button wasn't pressed within {} seconds; press it and run `LumiaLive pair` again",
+                    PAIRING_TIMEOUT_MS / 1000
+                )));
+            }
+            Err(e) => return Err(AppError::Bridge(format!("Pairing with the bridge failed: {}", e))),
+        }
+    };
+    info!("Paired with the bridge at {}", bridge.ip);
+    Ok(username)
+}
+
+/// The lights `backend` names. A dry run gets a placeholder bridge that is never contacted.
+fn controller_for(config: &Config) -> Result<Box<dyn LightController>, AppError> {
+    if config.dry_run {
+        info!("Dry run: not connecting to any lights, effects are only logged");
+        return Ok(Box::new(Bridge::for_ip([127, 0, 0, 1]).with_user(&config.credentials.hue.username)));
+    }
+    match config.backend {
+        Backend::Hue if config.credentials.hue.remote.enabled => Ok(Box::new(
+            hue_remote::HueRemoteController::connect(&config.credentials.hue.remote, &config.credentials.hue.username)?
+        )),
+        Backend::Hue => Ok(Box::new(connect_bridge(config)?)),
+        Backend::Lifx => Ok(Box::new(lifx::LifxController::discover(&config.lifx)?)),
+        Backend::Wled => Ok(Box::new(wled::WledController::connect(&config.wled)?)),
+    }
+}
+
+fn connect_bridge(config: &Config) -> Result<Bridge, AppError> {
+    info!("Connecting to Hue bridge...");
+    let bridge = if let Some(ip) = &config.credentials.hue.bridge_ip {
+        info!("Using configured bridge IP: {}", ip);
+        match Bridge::discover() {
+            Some(bridge) => bridge.with_user(&config.credentials.hue.username),
+            None => {
+                error!("Failed to discover bridge at configured IP");
+                return Err(AppError::Bridge("Failed to discover bridge".to_string()));
+            }
+        }
+    } else {
+        info!("No bridge IP configured, discovering bridge...");
+        Bridge::discover_required()
+            .with_user(&config.credentials.hue.username)
+    };
+
+    // Test bridge connection
+    match bridge.get_all_lights() {
+        Ok(lights) => info!("Successfully connected to bridge. Found {} lights", lights.len()),
+        Err(e) => {
+            error!("Failed to get lights from bridge: {}", e);
+            return Err(AppError::Bridge(format!("Failed to get lights: {}", e)));
+        }
+    }
+
+    Ok(bridge)
+}
+
+/// Fires every tier of a tiered event in ascending order so escalation can be checked by eye.
+fn run_test_tiers(config_path: &str, event_type: &str, dry_run: bool) -> Result<(), AppError> {
+    const TEST_DURATION_MS: u64 = 2000;
+
+    let mut config = load_config(config_path)?;
+    config.dry_run |= dry_run;
+    let mut tiers = config.events.tiers(event_type)
+        .ok_or_else(|| AppError::Bridge(format!("{} is not a tiered event type", event_type)))?
+        .to_vec();
+    tiers.sort_by(|a, b| a.amount.total_cmp(&b.amount));
+
+    let state = AppState::new(controller_for(&config)?, config);
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    for tier in tiers {
+        info!("Testing {} tier at amount {}", event_type, tier.amount);
+        let mut effect = tier.effect;
+        effect.duration = effect.duration.min(TEST_DURATION_MS);
+        rt.block_on(state.apply_effect(&effect))?;
+    }
+
+    info!("Finished testing {} tiers", event_type);
+    Ok(())
+}
+
+/// Builds the Streamlabs event for a `--simulate` spec: `donation:<amount>[:<currency>]`, `follow`,
+/// `subscription[:<months>]` or `bits:<amount>`.
+fn simulated_event(index: usize, spec: &str) -> Result<StreamlabsEvent, AppError> {
+    let mut parts = spec.split(':');
+    let kind = parts.next().unwrap_or_default();
+    let value = parts.next();
+    let amount = || value.ok_or_else(|| AppError::Config(format!("--simulate {} needs an amount, e.g. {}:50", kind, kind)));
+
+    let (event_type, event_for, mut message) = match kind {
+        "donation" => ("donation", None, serde_json::json!({
+            "amount": amount()?,
+            "currency": parts.next().unwrap_or("USD"),
+        })),
+        "follow" => ("follow", Some("twitch_account"), serde_json::json!({})),
+        "subscription" => {
+            let months: u32 = value.unwrap_or("1").parse()
+                .map_err(|_| AppError::Config(format!("Invalid months in --simulate {:?}", spec)))?;
+            ("subscription", Some("twitch_account"), serde_json::json!({ "months": months }))
+        }
+        "bits" => ("bits", Some("twitch_account"), serde_json::json!({ "amount": amount()? })),
+        _ => return Err(AppError::Config(format!(
+            "Unknown --simulate event {:?}, expected donation, follow, subscription or bits",
+            kind
+        ))),
+    };
+    message["name"] = "Simulated".into();
+
+    Ok(serde_json::from_value(serde_json::json!({
+        "event_id": format!("simulated-{}", index),
+        "for": event_for,
+        "type": event_type,
+        "message": [message],
+    }))?)
+}
+
+/// Feeds made-up events through the normal event handlers, one after another, playing the
+/// effects each one queues before moving on.
+fn run_simulation(config_path: &str, specs: &[String], dry_run: bool) -> Result<(), AppError> {
+    let events = specs.iter().enumerate()
+        .map(|(index, spec)| simulated_event(index, spec))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut config = load_config(config_path)?;
+    config.dry_run |= dry_run;
+    let state = AppState::new(controller_for(&config)?, config);
+    let mut queue = state.effect_queue.take_receiver()
+        .ok_or_else(|| AppError::Config("Effect queue is already taken".to_string()))?;
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let mqtt = state.config().mqtt.enabled.then(|| {
+        let config = state.config();
+        let (publisher, eventloop) = mqtt::MqttPublisher::new(&config.mqtt, config.credentials.mqtt.as_ref());
+        *state.mqtt.lock() = Some(publisher);
+        rt.spawn(mqtt::run(eventloop))
+    });
+    let discord = state.config().discord.enabled.then(|| {
+        let (notifier, poster) = discord::DiscordNotifier::new(&state.config().discord);
+        *state.discord.lock() = Some(notifier);
+        rt.spawn(poster)
+    });
+
+    for (spec, event) in specs.iter().zip(events) {
+        info!("Simulating {}", spec);
+        if let Err(e) = rt.block_on(state.handle_event(event)) {
+            error!("Error handling simulated {}: {}", spec, e);
+        }
+        while let Ok(queued) = queue.try_recv() {
+            rt.block_on(state.apply_effect(&queued.effect))?;
+        }
+    }
+
+    // Lets the webhook and MQTT connection send whatever is still queued before exiting
+    if let Some(poster) = discord {
+        drop(state.discord.lock().take());
+        let _ = rt.block_on(async { tokio::time::timeout(Duration::from_secs(10), poster).await });
+    }
+    if let Some(connection) = mqtt {
+        let publisher = state.mqtt.lock().take();
+        rt.block_on(async {
+            if let Some(publisher) = publisher {
+                publisher.close().await;
+            }
+            let _ = tokio::time::timeout(Duration::from_secs(5), connection).await;
+        });
+    }
+    info!("Finished simulating {} events", specs.len());
+    Ok(())
+}
+
+/// Latency at percentile `p` (0-100) of an ascending list of samples.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+/// Sends `rate` minimal commands per second, round-robin across the lights, for `duration_ms`
+/// and prints throughput, latency percentiles and failure counts.
+fn run_benchmark(config_path: &str, rate: u32, duration_ms: u64) -> Result<(), AppError> {
+    if rate == 0 {
+        return Err(AppError::Config("--rate must be at least 1".to_string()));
+    }
+    let config = load_config(config_path)?;
+    if config.backend != Backend::Hue {
+        return Err(AppError::Config("--benchmark only supports the Hue backend".to_string()));
+    }
+    if config.credentials.hue.remote.enabled {
+        return Err(AppError::Config("--benchmark needs the bridge on the LAN; it can't run with hue.remote enabled".to_string()));
+    }
+    // Reads the raw responses to tell rate limiting from rejection, so this talks to the bridge
+    // directly rather than through `LightController`
+    let bridge = connect_bridge(&config)?;
+    let lights = bridge.get_all_lights()
+        .map_err(|e| AppError::Bridge(format!("Failed to get lights: {}", e)))?;
+    if lights.is_empty() {
+        return Err(AppError::Bridge("No lights to benchmark".to_string()));
+    }
+    info!("Benchmarking {} commands/s across {} lights for {} ms", rate, lights.len(), duration_ms);
+
+    let interval = Duration::from_secs_f64(1.0 / rate as f64);
+    let start = Instant::now();
+    let mut next = start;
+    let (mut latencies, mut rate_limited, mut rejected, mut failed) = (Vec::new(), 0, 0, 0);
+    for sent in 0u64.. {
+        if start.elapsed() >= Duration::from_millis(duration_ms) {
+            break;
+        }
+        let light = &lights[sent as usize % lights.len()];
+        let command = CommandLight {
+            on: Some(true),
+            bri: Some(if sent % 2 == 0 { 254 } else { 128 }),
+            ..Default::default()
+        };
+        let sent_at = Instant::now();
+        match bridge.set_light_state(light.id, &command) {
+            Ok(response) => {
+                latencies.push(sent_at.elapsed());
+                match response_error(&response) {
+                    Some(e) if is_rate_limited(&e) => rate_limited += 1,
+                    Some(_) => rejected += 1,
+                    None => {}
+                }
+            }
+            Err(e) => {
+                debug!("Benchmark command to light {} failed: {}", light.id, e);
+                failed += 1;
+            }
+        }
+        // Fixed schedule: a slow bridge lowers the achieved rate instead of bunching commands up
+        next += interval;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
         }
     }
+    let elapsed = start.elapsed();
+
+    let total = latencies.len() + failed;
+    latencies.sort_unstable();
+    println!("{:<22}{:>10}", "Commands sent", total);
+    println!("{:<22}{:>10.1}", "Requested per second", rate as f64);
+    println!("{:<22}{:>10.1}", "Achieved per second", total as f64 / elapsed.as_secs_f64());
+    for (label, p) in [("Latency p50 (ms)", 50), ("Latency p90 (ms)", 90), ("Latency p99 (ms)", 99), ("Latency max (ms)", 100)] {
+        println!("{:<22}{:>10.1}", label, percentile(&latencies, p).as_secs_f64() * 1000.0);
+    }
+    println!("{:<22}{:>10}", "Rate limited", rate_limited);
+    println!("{:<22}{:>10}", "Rejected", rejected);
+    println!("{:<22}{:>10}", "Failed (no response)", failed);
+
+    AppState::new(bridge, config).reset_all_lights()
+}
+
+/// Removes `--flag <value>` or `--flag=<value>` from `args`, returning the value.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, AppError> {
+    let prefix = format!("{}=", flag);
+    if let Some(i) = args.iter().position(|arg| arg.starts_with(&prefix)) {
+        return Ok(Some(args.remove(i)[prefix.len()..].to_string()));
+    }
+    let Some(i) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(AppError::Config(format!("{} needs a value", flag)));
+    }
+    args.remove(i);
+    Ok(Some(args.remove(i)))
 }
 
 fn main() -> Result<(), AppError> {
+    // `--config`, `--log-level` and `--dry-run` apply to every mode, so they're taken out
+    // before the subcommand is looked at
+    let mut args: Vec<String> = std::env::args().collect();
+    let config_path = take_flag(&mut args, "--config")?;
+    let dry_run = args.iter().position(|arg| arg == "--dry-run").map(|i| args.remove(i)).is_some();
+    let log_level = match take_flag(&mut args, "--log-level")? {
+        Some(level) => level.parse().map_err(|_| AppError::Config(format!(
+            "Invalid --log-level {:?}, expected off, error, warn, info, debug or trace",
+            level
+        )))?,
+        None => log::LevelFilter::Debug,
+    };
     env_logger::builder()
-        .filter_level(log::LevelFilter::Debug)
+        .filter_level(log_level)
         .init();
+
+    // `--config <path>` reads a .json, .toml or .yaml/.yml config instead of config.json
+    let config_path = config_path.unwrap_or_else(|| CONFIG_PATH.to_string());
+    if args.get(1).map(String::as_str) == Some("pair") {
+        return pair_bridge(&mut load_config(&config_path)?);
+    }
+    // `--simulate <event>[:<amount>]`, repeatable, plays made-up events and exits
+    let mut simulate = Vec::new();
+    while let Some(spec) = take_flag(&mut args, "--simulate")? {
+        simulate.push(spec);
+    }
+    if !simulate.is_empty() {
+        return run_simulation(&config_path, &simulate, dry_run);
+    }
+    if args.get(1).map(String::as_str) == Some("test-tiers") {
+        let event_type = args.get(2).map(String::as_str).unwrap_or("donation");
+        return run_test_tiers(&config_path, event_type, dry_run);
+    }
+    // `--benchmark [--rate <per second>] [--duration <duration>]` load-tests the bridge and exits
+    if args.iter().any(|arg| arg == "--benchmark") {
+        let value = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+        let rate = match value("--rate") {
+            Some(rate) => rate.parse().map_err(|_| AppError::Config(format!("Invalid --rate {:?}", rate)))?,
+            None => 5,
+        };
+        let duration_ms = match value("--duration") {
+            Some(duration) => duration::parse_millis(duration).map_err(AppError::Config)?,
+            None => 30_000,
+        };
+        return run_benchmark(&config_path, rate, duration_ms);
+    }
+    // `--show <name>` starts with that show active, overriding `active_show`
+    let show = args.iter()
+        .position(|arg| arg == "--show")
+        .and_then(|i| args.get(i + 1))
+        .cloned();
     
     info!("Starting LumiaLive...");
     
-    let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
+    let (control_tx, control_rx) = std::sync::mpsc::channel();
+    let socket_control_tx = control_tx.clone();
 
     std::thread::spawn(move || {
-        info!("Loading configuration...");
-        let config: Config = match fs::read_to_string("config.json") {
-            Ok(content) => match serde_json::from_str(&content) {
-                Ok(config) => config,
-                Err(e) => {
-                    error!("Failed to parse config.json: {}", e);
-                    return;
-                }
-            },
+        let mut config = match load_config(&config_path) {
+            Ok(config) => config,
             Err(e) => {
-                error!("Failed to read config.json: {}", e);
+                error!("Failed to load {}: {}", config_path, e);
                 return;
             }
         };
+        if let Some(show) = &show {
+            if let Err(e) = config.select_show(Some(show)) {
+                error!("{}", e);
+                return;
+            }
+            info!("Starting with show {}", show);
+        }
+        config.dry_run |= dry_run;
+        if config.credentials.hue.username.is_empty() && config.backend == Backend::Hue && !config.dry_run {
+            info!("No Hue username configured, pairing with the bridge");
+            if let Err(e) = pair_bridge(&mut config) {
+                error!("{}", e);
+                return;
+            }
+        }
 
         let (event_tx, event_rx) = mpsc::channel::<StreamlabsEvent>(32);
+        let (parse_error_tx, parse_error_rx) = mpsc::channel::<()>(1);
 
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -380,91 +4652,63 @@ fn main() -> Result<(), AppError> {
         
         info!("Using socket URL: {}", socket_url);
 
-        let client = match ClientBuilder::new(&socket_url)
-            .transport_type(rust_socketio::TransportType::Websocket)
-            .on("connect", |_, _| {
-                info!("Socket.IO connected successfully!");
-            })
-            .on("disconnect", |_, _| {
-                warn!("Socket.IO disconnected!");
-            })
-            .on("connect_error", |err, _| {
-                error!("Socket.IO connection error: {:?}", err);
-            })
-            .on("event", {
-                let tx = event_tx.clone();
-                move |payload: Payload, _| {
-                    info!("Received raw socket event");
-                    match payload {
-                        Payload::String(message) => {
-                            info!("Processing String payload: {}", message);
-                            process_event(&message, &tx);
-                        }
-                        Payload::Text(json_value) => {
-                            info!("Processing Text payload: {:?}", json_value);
-                            if let Some(first_event) = json_value.first() {
-                                if let Ok(message) = serde_json::to_string(first_event) {
-                                    process_event(&message, &tx);
-                                } else {
-                                    error!("Failed to serialize JSON value to string");
-                                }
-                            } else {
-                                error!("Text payload is not an array");
-                            }
-                        }
-                        other => {
-                            warn!("Received unexpected payload type: {:?}", other);
-                        }
-                    }
-                }
-            })
-            .connect() {
-                Ok(client) => client,
-                Err(e) => {
-                    error!("Failed to connect to Streamlabs: {}", e);
-                    return;
-                }
-            };
+        let connected = Arc::new(AtomicBool::new(false));
+        let connect = || connect_streamlabs(&socket_url, &event_tx, &parse_error_tx, &socket_control_tx, &connected);
+        let Some(mut client) = connect_with_backoff(connect, &control_rx, || {
+            warn!("Lights aren't set up yet, ignoring reset");
+        }) else {
+            info!("Shutdown requested before Streamlabs connected");
+            return;
+        };
 
         info!("Connected to Streamlabs!");
 
         // Initialize Hue bridge and state
-        let state = rt.block_on(async {
-            info!("Connecting to Hue bridge...");
-            let bridge = if let Some(ip) = &config.credentials.hue.bridge_ip {
-                info!("Using configured bridge IP: {}", ip);
-                match Bridge::discover() {
-                    Some(bridge) => bridge.with_user(&config.credentials.hue.username),
-                    None => {
-                        error!("Failed to discover bridge at configured IP");
-                        return Err(AppError::Bridge("Failed to discover bridge".to_string()));
-                    }
-                }
-            } else {
-                info!("No bridge IP configured, discovering bridge...");
-                Bridge::discover_required()
-                    .with_user(&config.credentials.hue.username)
-            };
+        let state = Arc::new(AppState::new(
+            controller_for(&config).expect("Failed to initialize lights"),
+            config.clone(),
+        ));
+
+        state.streamlabs_connected.store(true, Ordering::SeqCst);
+        if let Err(e) = state.validate_light_groups() {
+            error!("Failed to validate light groups: {}", e);
+        }
+
+        if let Some(ramp_ms) = config.startup_ramp_ms {
+            if let Err(e) = state.ramp_to_default(ramp_ms) {
+                error!("Failed to ramp lights to the default state: {}", e);
+            }
+        }
+
+        if config.api.enabled {
+            rt.spawn(api::serve(state.clone(), config.api.clone()));
+        }
+
+        if config.metrics.enabled {
+            rt.spawn(metrics::serve(state.clone(), config.metrics.clone()));
+        }
+
+        if config.mqtt.enabled {
+            info!("Publishing events to MQTT broker {}:{} on {}", config.mqtt.host, config.mqtt.port, config.mqtt.topic);
+            let (publisher, eventloop) = mqtt::MqttPublisher::new(&config.mqtt, config.credentials.mqtt.as_ref());
+            rt.spawn(mqtt::run(eventloop));
+            *state.mqtt.lock() = Some(publisher);
+        }
 
-            let bridge = Arc::new(Mutex::new(bridge));
-            
-            // Test bridge connection
-            {
-                let bridge_lock = bridge.lock();
-                match bridge_lock.get_all_lights() {
-                    Ok(lights) => info!("Successfully connected to bridge. Found {} lights", lights.len()),
-                    Err(e) => {
-                        error!("Failed to get lights from bridge: {}", e);
-                        return Err(AppError::Bridge(format!("Failed to get lights: {}", e)));
-                    }
-                }
+        if config.discord.enabled {
+            info!("Posting events to Discord");
+            let (notifier, poster) = discord::DiscordNotifier::new(&config.discord);
+            rt.spawn(poster);
+            *state.discord.lock() = Some(notifier);
+        }
+
+        let _config_watcher = match watch_config(state.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                error!("Failed to watch {} for changes, edits need a restart: {}", config.path, e);
+                None
             }
-            
-            Ok::<_, AppError>(Arc::new(AppState {
-                bridge: bridge.clone(),
-                config: config.clone(),
-            }))
-        }).expect("Failed to initialize bridge");
+        };
 
         // Spawn event handler
         let _event_handler = {
@@ -473,7 +4717,27 @@ fn main() -> Result<(), AppError> {
             let rt_handle = rt.handle().clone();
             rt.spawn_blocking(move || {
                 info!("Event handler thread started");
-                while let Some(event) = event_rx.blocking_recv() {
+                let mut sampler = EventSampler::default();
+                loop {
+                    let event = match sampler.next_slot() {
+                        // Wait for the next event or for the rate window to free a slot
+                        Some(wait) => match rt_handle.block_on(tokio::time::timeout(wait, event_rx.recv())) {
+                            Ok(Some(event)) => event,
+                            Ok(None) => break,
+                            Err(_) => match sampler.take_sample(|events| state.pick_sample(events)) {
+                                Some(event) => event,
+                                None => continue,
+                            },
+                        },
+                        None => match event_rx.blocking_recv() {
+                            Some(event) => event,
+                            None => break,
+                        },
+                    };
+                    let Some(event) = sampler.admit(event, &state.config().rate_cap) else {
+                        continue;
+                    };
+
                     info!("Processing event in handler: {:?}", event);
                     if let Err(e) = rt_handle.block_on(state.handle_event(event)) {
                         error!("Error handling event: {}", e);
@@ -483,34 +4747,1048 @@ fn main() -> Result<(), AppError> {
             })
         };
 
+        // Spawn effect queue consumer
+        let _effect_queue = {
+            let state = state.clone();
+            let rt_handle = rt.handle().clone();
+            rt.spawn_blocking(move || rt_handle.block_on(state.run_effect_queue()))
+        };
+
+        // Spawn idle watchdog
+        let _idle_watchdog = if config.idle_watchdog.enabled {
+            let state = state.clone();
+            let interval = Duration::from_millis(config.idle_watchdog.idle_ms.clamp(1_000, 30_000));
+            Some(rt.spawn_blocking(move || loop {
+                std::thread::sleep(interval);
+                if let Err(e) = state.check_idle_lights() {
+                    error!("Idle watchdog check failed: {}", e);
+                }
+            }))
+        } else {
+            None
+        };
+
+        // Spawn light check
+        let _light_check = config.light_check_interval_ms.map(|interval_ms| {
+            let state = state.clone();
+            let interval = Duration::from_millis(interval_ms.max(1_000));
+            rt.spawn_blocking(move || loop {
+                std::thread::sleep(interval);
+                if let Err(e) = state.validate_light_groups() {
+                    error!("Failed to validate light groups: {}", e);
+                }
+            })
+        });
+
+        let _scheduler = if config.scheduled_effects.is_empty() {
+            None
+        } else {
+            let state = state.clone();
+            let schedule = config.scheduled_effects.clone();
+            let rt_handle = rt.handle().clone();
+            Some(rt.spawn_blocking(move || rt_handle.block_on(state.run_schedule(schedule))))
+        };
+
+        // Spawn parse error flasher
+        let _parse_error_flasher = {
+            let state = state.clone();
+            let mut parse_error_rx = parse_error_rx;
+            let rt_handle = rt.handle().clone();
+            rt.spawn_blocking(move || {
+                while parse_error_rx.blocking_recv().is_some() {
+                    if let Err(e) = rt_handle.block_on(state.flash_parse_error()) {
+                        error!("Error flashing for parse error: {}", e);
+                    }
+                }
+            })
+        };
+
         info!("System ready! Waiting for events...");
 
-        // Wait for shutdown signal
-        match shutdown_rx.recv() {
-            Ok(_) => info!("Received shutdown signal"),
-            Err(e) => error!("Shutdown receiver error: {}", e),
+        // Handle console commands until shutdown
+        loop {
+            match control_rx.recv() {
+                Ok(Control::ResetLights) => reset_lights(&state),
+                Ok(Control::SocketLost) => {
+                    state.streamlabs_connected.store(false, Ordering::SeqCst);
+                    if let Err(e) = client.disconnect() {
+                        debug!("Error closing the lost Streamlabs socket: {}", e);
+                    }
+                    match connect_with_backoff(connect, &control_rx, || reset_lights(&state)) {
+                        Some(reconnected) => {
+                            client = reconnected;
+                            state.streamlabs_connected.store(true, Ordering::SeqCst);
+                            state.metrics.socket_reconnected();
+                            info!("Reconnected to Streamlabs!");
+                        }
+                        None => {
+                            state.shutting_down.store(true, Ordering::SeqCst);
+                            break;
+                        }
+                    }
+                }
+                Ok(Control::Shutdown) => {
+                    info!("Received shutdown signal");
+                    state.shutting_down.store(true, Ordering::SeqCst);
+                    break;
+                }
+                Err(e) => {
+                    error!("Shutdown receiver error: {}", e);
+                    break;
+                }
+            }
         }
 
         // Clean shutdown
         info!("Shutdown signal received, cleaning up...");
         drop(event_tx);
-        if let Err(e) = client.disconnect() {
-            error!("Error during disconnect: {}", e);
+        drop(parse_error_tx);
+        // A lost socket was already closed before reconnecting
+        if connected.load(Ordering::SeqCst) {
+            if let Err(e) = client.disconnect() {
+                error!("Error during disconnect: {}", e);
+            }
         }
         info!("Shutdown complete");
     });
 
-    // Main thread just waits for ctrl-c
-    info!("Press Enter to exit...");
-    if let Err(e) = std::io::stdin().read_line(&mut String::new()) {
-        error!("Error waiting for input: {}", e);
+    // Main thread runs a small console: r resets the lights, q, Enter or EOF quits
+    info!("Type r + Enter to reset all lights, q or Enter to exit...");
+    loop {
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => match line.trim() {
+                "r" => {
+                    if let Err(e) = control_tx.send(Control::ResetLights) {
+                        error!("Error sending reset command: {}", e);
+                    }
+                }
+                "" | "q" => break,
+                other => warn!("Unknown command {:?}: r resets all lights, q or Enter exits", other),
+            },
+            Err(e) => {
+                error!("Error waiting for input: {}", e);
+                break;
+            }
+        }
     }
     
     // Signal shutdown
     info!("Sending shutdown signal...");
-    if let Err(e) = shutdown_tx.send(()) {
+    if let Err(e) = control_tx.send(Control::Shutdown) {
         error!("Error sending shutdown signal: {}", e);
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use controller::mock::{MockController, Target};
+
+    fn effect(brightness: u8) -> LightEffect {
+        LightEffect {
+            color: "#ff0000".to_string(),
+            brightness,
+            alert: "select".to_string(),
+            duration: 1000,
+            ..Default::default()
+        }
+    }
+
+    fn config_with(zero_brightness: Option<&str>, follow_brightness: u8) -> Config {
+        let effect = serde_json::to_value(effect(follow_brightness)).unwrap();
+        let tiered = serde_json::json!({ "enabled": true, "tiers": [] });
+        let mut config = serde_json::json!({
+            "credentials": { "streamlabs": {}, "hue": {} },
+            "default_state": { "on": true, "brightness": 254, "hue": 8000, "saturation": 140, "alert": "none" },
+            "events": {
+                "donation": tiered,
+                "twitch_follow": { "enabled": true, "effect": effect },
+                "twitch_subscription": { "enabled": true, "tiers": [{ "amount": 1, "effect": effect }] },
+                "twitch_bits": tiered,
+            },
+        });
+        if let Some(behavior) = zero_brightness {
+            config["zero_brightness"] = behavior.into();
+        }
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn zero_brightness_turns_lights_off() {
+        let command = effect_command(&effect(0), 0, 254);
+        assert_eq!(command.on, Some(false));
+        assert_eq!(command.bri, None);
+        assert_eq!(command.alert, None);
+    }
+
+    #[test]
+    fn nonzero_brightness_turns_lights_on() {
+        let command = effect_command(&effect(1), 0, 254);
+        assert_eq!(command.on, Some(true));
+        assert_eq!(command.bri, Some(1));
+    }
+
+    #[test]
+    fn zero_brightness_defaults_to_off() {
+        let config = config_with(None, 0);
+        assert_eq!(config.zero_brightness, ZeroBrightness::Off);
+        assert!(config.check_zero_brightness().is_ok());
+    }
+
+    #[test]
+    fn zero_brightness_reject_fails_validation() {
+        assert!(matches!(config_with(Some("reject"), 0).check_zero_brightness(), Err(AppError::Config(_))));
+        assert!(config_with(Some("reject"), 10).check_zero_brightness().is_ok());
+    }
+
+    #[test]
+    fn validate_lists_every_problem() {
+        assert!(config_with(None, 254).validate().is_ok());
+
+        let mut config = config_with(None, 254);
+        config.default_state.saturation = 255;
+        config.events.twitch_follow.effect.brightness = 255;
+        config.events.twitch_follow.effect.alert = "blink".to_string();
+        let Err(AppError::Config(message)) = config.validate() else {
+            panic!("expected a config error");
+        };
+        assert!(message.starts_with("3 invalid value(s)"), "{}", message);
+        assert!(message.contains("brightness 255") && message.contains("saturation 255") && message.contains("\"blink\""));
+    }
+
+    #[test]
+    fn command_retry_backoff_doubles_up_to_the_cap() {
+        let mut config = config_with(None, 254);
+        config.command_retry.reset.max_retries = u32::MAX;
+        let state = AppState::new(MockController::default(), config);
+        let error: ControllerError = Box::new(hueclient::HueError::ProtocolError { msg: "timed out".to_string() });
+        let delay = |attempt| state.retry_delay(CommandKind::Reset, attempt, &error);
+        assert_eq!(delay(0), Some(250));
+        assert_eq!(delay(3), Some(2_000));
+        assert_eq!(delay(20), Some(COMMAND_RETRY_MAX_MS));
+        // Past a 64-bit shift, with no overflow panic
+        assert_eq!(delay(200), Some(COMMAND_RETRY_MAX_MS));
+    }
+
+    #[test]
+    fn rediscovery_backoff_doubles_up_to_the_cap() {
+        let rediscovery = BridgeRediscoveryConfig {
+            retry_delay_ms: 2_000,
+            max_delay_ms: 60_000,
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (0..7).map(|attempt| rediscovery.retry_delay(attempt)).collect();
+        assert_eq!(delays, [0, 2_000, 4_000, 8_000, 16_000, 32_000, 60_000]);
+        // Past a 64-bit shift, and a huge configured delay, with no overflow panic
+        assert_eq!(rediscovery.retry_delay(200), 60_000);
+        let huge = BridgeRediscoveryConfig { retry_delay_ms: u64::MAX / 2, ..rediscovery };
+        assert_eq!(huge.retry_delay(5), 60_000);
+    }
+
+    #[test]
+    fn edits_go_to_the_active_show() {
+        let mut config = config_with(None, 254);
+        config.shows.insert("finale".to_string(), config.events.clone());
+        assert_eq!(config.active_event_source(), EventSource::Events);
+
+        config.select_show(Some("finale")).unwrap();
+        let source = config.active_event_source();
+        assert_eq!(source, EventSource::Show("finale".to_string()));
+        config.events_mut(&source).twitch_follow.effect.color = "#00ff00".to_string();
+        assert_eq!(config.active_events().twitch_follow.effect.color, "#00ff00");
+        assert_eq!(config.events.twitch_follow.effect.color, "#ff0000");
+    }
+
+    fn light(id: usize) -> IdentifiedLight {
+        IdentifiedLight {
+            id,
+            light: hueclient::Light {
+                name: format!("Light {}", id),
+                modelid: String::new(),
+                swversion: String::new(),
+                uniqueid: String::new(),
+                state: hueclient::LightState { on: true, bri: Some(254), hue: None, sat: None, ct: None, xy: None },
+            },
+        }
+    }
+
+    fn assert_hue_sat(color: &str, hue: u16, sat: u8) {
+        let (actual_hue, actual_sat) = hex_to_hue(color).unwrap();
+        assert!(actual_hue.abs_diff(hue) <= 100, "{} hue {} != {}", color, actual_hue, hue);
+        assert!(actual_sat.abs_diff(sat) <= 2, "{} sat {} != {}", color, actual_sat, sat);
+    }
+
+    #[test]
+    fn hex_to_hue_known_colors() {
+        assert_hue_sat("#ff0000", 0, 254);
+        assert_hue_sat("#808080", 0, 0);
+        assert_hue_sat("#008080", 32767, 254);
+    }
+
+    #[test]
+    fn hex_to_hue_decodes_srgb_gamma() {
+        // Raw channels would give #ff8080 a saturation of ~127; in linear light it is ~199
+        assert_hue_sat("#ff8080", 0, 199);
+    }
+
+    fn assert_rgb(color: &str, expected: (f32, f32, f32)) {
+        let (r, g, b) = parse_color_rgb(color).unwrap();
+        let close = |a: f32, b: f32| (a - b).abs() < 0.001;
+        assert!(close(r, expected.0) && close(g, expected.1) && close(b, expected.2), "{}: {:?}", color, (r, g, b));
+    }
+
+    #[test]
+    fn rgb_and_hsl_functions_parse_with_any_spacing() {
+        assert_rgb("rgb(255, 0, 128)", (1.0, 0.0, 128.0 / 255.0));
+        assert_rgb("  RGB( 255 ,0,  128 ) ", (1.0, 0.0, 128.0 / 255.0));
+        assert_rgb("rgb(127.5, 0, 0)", (0.5, 0.0, 0.0));
+        assert_rgb("hsl(210, 100%, 50%)", (0.0, 0.5, 1.0));
+        assert_rgb(" hsl( 210deg ,100% , 50% )", (0.0, 0.5, 1.0));
+        assert_rgb("hsl(0, 0%, 100%)", (1.0, 1.0, 1.0));
+        assert_rgb("hsl(120, 100%, 25%)", (0.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn rgb_and_hsl_functions_reject_bad_components() {
+        for color in [
+            "rgb(256, 0, 0)",
+            "rgb(-1, 0, 0)",
+            "rgb(255, 0)",
+            "rgb(255, 0, 0, 0)",
+            "rgb(red, 0, 0)",
+            "rgb(255, 0, 0",
+            "hsl(361, 50%, 50%)",
+            "hsl(0, 101%, 50%)",
+            "hsl(0, 50%, -5%)",
+            // Saturation and lightness have to be percentages
+            "hsl(0, 50, 50)",
+        ] {
+            assert!(parse_color_rgb(color).is_err(), "{} parsed", color);
+        }
+    }
+
+    #[test]
+    fn blending_reads_every_color_syntax() {
+        assert_eq!(blend_colors("rgb(255, 0, 0)", "hsl(240, 100%, 50%)", 0.5).unwrap(), "#800080");
+        assert_eq!(blend_colors("red", "#0000ff", 0.25).unwrap(), "#bf0040");
+        assert_eq!(blend_colors("hsl(0, 0%, 0%)", "white", 2.0).unwrap(), "#ffffff");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bridge_is_free_while_an_effect_waits() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = AppState::new(mock.clone(), config_with(None, 254));
+        let effect = LightEffect {
+            duration: 300,
+            ..effect(254)
+        };
+
+        let playing = state.apply_effect(&effect);
+        let second_operation = async {
+            sleep(Duration::from_millis(100)).await;
+            state.controller.try_lock().is_some()
+        };
+        let (result, bridge_free) = tokio::join!(playing, second_operation);
+        assert!(result.is_ok());
+        assert!(bridge_free, "the bridge stayed locked during the effect's wait");
+        // The effect and its reset were both sent, around the wait
+        let sent = mock.sent();
+        assert!(sent.len() >= 2, "{:?}", sent.len());
+        assert_eq!(sent.last().unwrap().at - sent[0].at, Duration::from_millis(300));
+    }
+
+    #[test]
+    fn removed_target_is_skipped() {
+        let mut config = config_with(None, 254);
+        config.light_groups.insert("desk".to_string(), vec![1, 2, 3]);
+        let bridge = Bridge::for_ip([127, 0, 0, 1]).with_user("test");
+        let state = AppState::new(bridge, config);
+        let desk = LightEffect {
+            light_group: Some("desk".to_string()),
+            ..effect(254)
+        };
+
+        let targets = state.resolve_targets(&desk, vec![light(1), light(2), light(3)]);
+        assert_eq!(targets.iter().map(|light| light.id).collect::<Vec<_>>(), [1, 2, 3]);
+
+        // Light 2 was removed from the bridge but is still listed in the group
+        let known = state.known_lights.lock().clone();
+        let targets = state.resolve_targets(&desk, vec![light(1), light(3)]);
+        assert_eq!(targets.iter().map(|light| light.id).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(removed_lights(&known, &state.known_lights.lock()), [2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn light_removed_during_an_effect_is_skipped() {
+        let mock = MockController::with_lights(&[1, 2, 3]);
+        let mut config = config_with(None, 254);
+        config.light_groups.insert("desk".to_string(), vec![1, 2, 3]);
+        let state = AppState::new(mock.clone(), config);
+        let desk = LightEffect {
+            light_group: Some("desk".to_string()),
+            ..effect(254)
+        };
+
+        mock.fail_light(2);
+        state.apply_effect(&desk).await.unwrap();
+
+        let sent = mock.sent();
+        let targets: Vec<Target> = sent.iter().map(|sent| sent.target).collect();
+        assert_eq!(targets, [Target::Light(1), Target::Light(3), Target::Light(1), Target::Light(3)]);
+        assert_eq!(sent[0].command.bri, Some(254));
+        for reset in &sent[2..] {
+            assert_default_state(&reset.command);
+        }
+        assert!(!state.known_lights.lock().contains(&2));
+    }
+
+    fn tiers(amounts: &[f64]) -> Vec<TierEffect> {
+        amounts.iter().map(|&amount| TierEffect { amount, effect: effect(amount as u8) }).collect()
+    }
+
+    fn selected(tiers: &[TierEffect], amount: f64) -> u8 {
+        let bridge = Bridge::for_ip([127, 0, 0, 1]).with_user("test");
+        let state = AppState::new(bridge, config_with(None, 254));
+        state.select_tier(tiers, amount).unwrap().brightness
+    }
+
+    #[test]
+    fn highest_reached_tier_wins_in_any_order() {
+        for amounts in [[100.0, 10.0, 1.0], [1.0, 10.0, 100.0], [10.0, 100.0, 1.0]] {
+            let tiers = tiers(&amounts);
+            assert_eq!(selected(&tiers, 100.0), 100, "{:?}", amounts);
+            assert_eq!(selected(&tiers, 250.0), 100, "{:?}", amounts);
+            assert_eq!(selected(&tiers, 50.0), 10, "{:?}", amounts);
+            assert_eq!(selected(&tiers, 1.0), 1, "{:?}", amounts);
+        }
+    }
+
+    #[test]
+    fn amount_below_every_tier_plays_the_lowest() {
+        assert_eq!(selected(&tiers(&[1.0, 10.0, 5.0]), 0.5), 1);
+        assert_eq!(selected(&tiers(&[10.0, 5.0, 20.0]), 2.0), 5);
+    }
+
+    fn donation(amount: &str) -> EventMessage {
+        serde_json::from_value(serde_json::json!({ "name": "Tester", "amount": amount })).unwrap()
+    }
+
+    /// A state whose donation tiers are $5 (red, 2 s, desk lights only) and $50 (blue, 5 s).
+    fn donation_state(mock: &MockController) -> AppState {
+        let mut config = config_with(None, 254);
+        config.light_groups.insert("desk".to_string(), vec![2]);
+        let tier = |amount, color: &str, brightness, duration, light_group: Option<&str>| TierEffect {
+            amount,
+            effect: LightEffect {
+                color: color.to_string(),
+                duration,
+                light_group: light_group.map(str::to_string),
+                ..effect(brightness)
+            },
+        };
+        config.events.donation.tiers = vec![
+            tier(5.0, "#ff0000", 100, 2000, Some("desk")),
+            tier(50.0, "#0000ff", 254, 5000, None),
+        ];
+        AppState::new(mock.clone(), config)
+    }
+
+    /// Plays whatever the handlers queued, as `run_effect_queue` would.
+    async fn play_queued(state: &AppState) {
+        let mut queue = state.effect_queue.take_receiver().unwrap();
+        while let Ok(queued) = queue.try_recv() {
+            state.apply_effect(&queued.effect).await.unwrap();
+        }
+    }
+
+    fn assert_default_state(command: &CommandLight) {
+        assert_eq!((command.bri, command.hue, command.sat), (Some(254), Some(8000), Some(140)));
+        assert_eq!(command.alert.as_deref(), Some("none"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tiered_events_without_tiers_are_skipped() {
+        let mock = MockController::with_lights(&[1, 2]);
+        // Donations and bits are enabled with no tiers
+        let state = AppState::new(mock.clone(), config_with(None, 254));
+        state.handle_donation(&donation("10"), state.config().active_events()).await.unwrap();
+        state.handle_bits(&donation("100"), state.config().active_events()).await.unwrap();
+        play_queued(&state).await;
+        assert!(mock.sent().is_empty());
+    }
+
+    #[test]
+    fn amounts_parse_past_currency_symbols_codes_and_decimal_commas() {
+        for (raw, expected) in [
+            ("5", 5.0),
+            ("£5.00", 5.0),
+            ("$12.50", 12.5),
+            ("5,00 €", 5.0),
+            ("USD 10", 10.0),
+            ("10 CAD", 10.0),
+            ("1.234,56", 1234.56),
+            ("1,234.56", 1234.56),
+            ("1,234", 1234.0),
+            ("1.234.567,89 €", 1234567.89),
+            ("1,234,567", 1234567.0),
+            ("0,5", 0.5),
+        ] {
+            assert_eq!(parse_amount(raw), Some(expected), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn amounts_without_a_number_are_rejected() {
+        for raw in ["", "free", "€", "USD", "--", "1-2", "..,"] {
+            assert_eq!(parse_amount(raw), None, "{:?}", raw);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bits_amount_with_a_thousands_separator_picks_its_tier() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let mut config = config_with(None, 254);
+        config.events.twitch_bits.tiers = vec![
+            TierEffect { amount: 100.0, effect: effect(10) },
+            TierEffect { amount: 1000.0, effect: effect(200) },
+        ];
+        let state = AppState::new(mock.clone(), config);
+        state.handle_bits(&donation("1,000"), state.config().active_events()).await.unwrap();
+        play_queued(&state).await;
+        assert_eq!(mock.sent()[0].command.bri, Some(200));
+    }
+
+    fn mapping(min_amount: f64, max_amount: f64) -> ContinuousMapping {
+        ContinuousMapping {
+            min_amount,
+            max_amount,
+            min_brightness: 50,
+            max_brightness: 250,
+            min_duration: 1000,
+            max_duration: 11000,
+            color: Some("#9146ff".to_string()),
+            alert: None,
+        }
+    }
+
+    fn mapped(mapping: &ContinuousMapping, amount: f64) -> (u8, u64) {
+        let mut effect = effect(1);
+        mapping.apply(&mut effect, amount);
+        (effect.brightness, effect.duration)
+    }
+
+    #[test]
+    fn continuous_mapping_scales_within_its_range() {
+        let mapping = mapping(100.0, 1100.0);
+        assert_eq!(mapped(&mapping, 100.0), (50, 1000));
+        assert_eq!(mapped(&mapping, 600.0), (150, 6000));
+        assert_eq!(mapped(&mapping, 1100.0), (250, 11000));
+        // Clamped outside the range
+        assert_eq!(mapped(&mapping, 10.0), (50, 1000));
+        assert_eq!(mapped(&mapping, 50_000.0), (250, 11000));
+    }
+
+    #[test]
+    fn continuous_mapping_over_a_single_amount_plays_the_maximum() {
+        let mapping = mapping(500.0, 500.0);
+        assert_eq!(mapped(&mapping, 500.0), (250, 11000));
+        assert_eq!(mapped(&mapping, 1.0), (250, 11000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn continuous_bits_without_tiers_play_the_mapping_color() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let mut config = config_with(None, 254);
+        config.events.twitch_bits.continuous = Some(mapping(100.0, 1100.0));
+        let state = AppState::new(mock.clone(), config);
+        state.handle_bits(&donation("600"), state.config().active_events()).await.unwrap();
+        play_queued(&state).await;
+
+        let sent = mock.sent();
+        assert_eq!((sent[0].command.bri, sent[0].command.alert.as_deref()), (Some(150), Some("select")));
+        assert_eq!(sent[1].at - sent[0].at, Duration::from_millis(6000));
+    }
+
+    #[test]
+    fn continuous_bits_without_tiers_or_color_fail_validation() {
+        let mut config = config_with(None, 254);
+        config.events.twitch_bits.continuous = Some(ContinuousMapping { color: None, ..mapping(100.0, 1100.0) });
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("twitch_bits continuous has no tiers"), "{}", error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn youtube_amounts_in_micros_pick_their_tier() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let mut config = config_with(None, 254);
+        let youtube_tiers = vec![
+            TierEffect { amount: 5.0, effect: effect(10) },
+            TierEffect { amount: 50.0, effect: effect(200) },
+        ];
+        config.events.youtube_superchat.tiers = youtube_tiers.clone();
+        config.events.youtube_super_sticker.tiers = youtube_tiers;
+        let state = AppState::new(mock.clone(), config);
+
+        // $20 and $50 in millionths, the second with a thousands separator
+        state.handle_superchat(&donation("20000000"), state.config().active_events()).await.unwrap();
+        state.handle_super_sticker(&donation("50,000,000"), state.config().active_events()).await.unwrap();
+        play_queued(&state).await;
+
+        let brightness: Vec<Option<u8>> = mock.sent().iter()
+            .filter(|sent| sent.command.alert.as_deref() == Some("select"))
+            .map(|sent| sent.command.bri)
+            .collect();
+        assert_eq!(brightness, [Some(10), Some(200)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resubs_pick_the_tier_for_their_cumulative_months() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let mut config = config_with(None, 254);
+        config.events.twitch_subscription.tiers = vec![
+            TierEffect { amount: 1.0, effect: effect(10) },
+            TierEffect { amount: 6.0, effect: effect(100) },
+            TierEffect { amount: 12.0, effect: effect(200) },
+        ];
+        let state = AppState::new(mock.clone(), config);
+        for (index, months) in ["1", "7", "24"].iter().enumerate() {
+            let event = simulated_event(index, &format!("subscription:{}", months)).unwrap();
+            state.handle_twitch_subscription(&event, state.config().active_events()).await.unwrap();
+        }
+        play_queued(&state).await;
+
+        let brightness: Vec<Option<u8>> = mock.sent().iter()
+            .filter(|sent| sent.command.alert.as_deref() == Some("select"))
+            .map(|sent| sent.command.bri)
+            .collect();
+        assert_eq!(brightness, [Some(10), Some(100), Some(200)]);
+    }
+
+    #[test]
+    fn flat_subscription_config_points_at_the_tiered_shape() {
+        let config = serde_json::json!({
+            "credentials": { "streamlabs": {}, "hue": {} },
+            "default_state": { "on": true, "brightness": 254, "hue": 8000, "saturation": 140, "alert": "none" },
+            "events": {
+                "twitch_subscription": { "enabled": true, "effect": effect(254) },
+            },
+        });
+        let error = serde_json::from_value::<Config>(config).unwrap_err().to_string();
+        assert!(error.contains("twitch_subscription is now tiered on cumulative months"), "{}", error);
+    }
+
+    /// A gifted subscription event from `gifter` carrying `gift_count` as sent.
+    fn gift_event(index: usize, gift_count: serde_json::Value) -> StreamlabsEvent {
+        serde_json::from_value(serde_json::json!({
+            "event_id": format!("gift-{}", index),
+            "for": "twitch_account",
+            "type": "subscription",
+            "message": [{ "name": "Recipient", "gifter": "Gifter", "gift_count": gift_count }],
+        })).unwrap()
+    }
+
+    fn gift_state(mock: &MockController) -> AppState {
+        let mut config = config_with(None, 254);
+        config.events.twitch_gift_subscription = EventTieredEffect {
+            enabled: true,
+            tiers: vec![
+                TierEffect { amount: 1.0, effect: effect(10) },
+                TierEffect { amount: 5.0, effect: effect(100) },
+                TierEffect { amount: 20.0, effect: effect(200) },
+            ],
+            ..Default::default()
+        };
+        AppState::new(mock.clone(), config)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gifted_subs_pick_the_tier_for_their_count() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = gift_state(&mock);
+        for (index, count) in [serde_json::json!(1), serde_json::json!("5"), serde_json::json!(50)].into_iter().enumerate() {
+            state.handle_twitch_subscription(&gift_event(index, count), state.config().active_events()).await.unwrap();
+        }
+        play_queued(&state).await;
+
+        let brightness: Vec<Option<u8>> = mock.sent().iter()
+            .filter(|sent| sent.command.alert.as_deref() == Some("select"))
+            .map(|sent| sent.command.bri)
+            .collect();
+        assert_eq!(brightness, [Some(10), Some(100), Some(200)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gift_count_that_does_not_parse_plays_the_lowest_tier() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = gift_state(&mock);
+        state.handle_twitch_subscription(&gift_event(0, "a bunch".into()), state.config().active_events()).await.unwrap();
+        play_queued(&state).await;
+
+        assert_eq!(mock.sent()[0].command.bri, Some(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn random_lights_are_seeded_and_reset_together() {
+        let mock = MockController::with_lights(&[1, 2, 3, 4, 5]);
+        let mut config = config_with(None, 254);
+        config.random_seed = Some(42);
+        let state = AppState::new(mock.clone(), config);
+        let random = |count| LightEffect { random_light_count: Some(count), ..effect(200) };
+
+        state.apply_effect(&random(2)).await.unwrap();
+        let targets: Vec<Target> = mock.sent().iter().map(|sent| sent.target).collect();
+        assert_eq!(targets, [Target::Light(2), Target::Light(3), Target::Light(2), Target::Light(3)]);
+        assert_eq!(mock.sent()[0].command.bri, Some(200));
+        assert_default_state(&mock.sent()[2].command);
+
+        // More than there are picks every light once
+        let before = mock.sent().len();
+        state.apply_effect(&random(9)).await.unwrap();
+        let targets: Vec<Target> = mock.sent()[before..].iter().map(|sent| sent.target).collect();
+        let every_light: Vec<Target> = (1..=5).map(Target::Light).collect();
+        assert_eq!(targets, [every_light.clone(), every_light].concat());
+    }
+
+    // Streamlabs socket payloads as recorded, trimmed of fields LumiaLive doesn't read
+    const RECORDED_EVENTS: [&str; 3] = [
+        r#"{"type":"donation","message":[{"name":"Tester","amount":"25.00","formatted_amount":"$25.00","currency":"USD","message":"Great stream!","_id":"d1"}],"event_id":"evt-donation"}"#,
+        r#"{"type":"subscription","for":"twitch_account","message":[{"name":"Subber","months":3,"sub_plan":"1000","_id":"s1"}],"event_id":"evt-sub"}"#,
+        r#"{"type":"bits","for":"twitch_account","message":[{"name":"Cheerer","amount":"500","_id":"b1"}],"event_id":"evt-bits"}"#,
+    ];
+
+    fn pipeline_config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "credentials": { "streamlabs": {}, "hue": {} },
+            "default_state": { "on": true, "brightness": 254, "hue": 8000, "saturation": 140, "alert": "none" },
+            "events": {
+                "donation": { "enabled": true, "tiers": [
+                    { "amount": 5, "effect": { "color": "#ff0000", "brightness": 200, "alert": "select", "duration": "2s", "hold_after_ms": "1s" } },
+                ] },
+                "twitch_subscription": { "enabled": true, "tiers": [
+                    { "amount": 1, "effect": { "color": "#00ff00", "brightness": 150, "alert": "lselect", "duration": "20s", "sustain_alert": true } },
+                ] },
+                "twitch_bits": { "enabled": true, "tiers": [
+                    { "amount": 100, "effect": { "color": "#0000ff", "brightness": 100, "alert": "select", "duration": "1s" } },
+                ] },
+                "twitch_follow": { "enabled": false, "effect": { "color": "#ffffff", "brightness": 254, "alert": "none", "duration": "1s" } },
+            },
+        })).unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recorded_events_play_their_effects_then_reset() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = AppState::new(mock.clone(), pipeline_config());
+
+        let (tx, mut rx) = mpsc::channel(RECORDED_EVENTS.len());
+        let (parse_errors, _) = mpsc::channel(1);
+        tokio::task::spawn_blocking(move || {
+            for message in RECORDED_EVENTS {
+                process_event(message, &tx, &parse_errors);
+            }
+        }).await.unwrap();
+        while let Some(event) = rx.recv().await {
+            state.handle_event(event).await.unwrap();
+        }
+        tokio::select! {
+            _ = state.run_effect_queue() => unreachable!("the effect queue stays open"),
+            _ = sleep(Duration::from_secs(60)) => {}
+        }
+
+        // Effect, hold (the stop of the alert, or the re-triggered lselect), then the reset, per event
+        let sent = mock.sent();
+        let start = sent[0].at;
+        let commands: Vec<_> = sent.iter().map(|sent| {
+            let command = &sent.command;
+            let at = (sent.at - start).as_millis();
+            (at, sent.target, command.on, command.bri, command.hue, command.sat, command.alert.as_deref())
+        }).collect();
+        let reset = |at, id| (at, Target::Light(id), Some(true), Some(254), Some(8000), Some(140), Some("none"));
+        let alert = |at, id, alert| (at, Target::Light(id), None, None, None, None, Some(alert));
+        assert_eq!(commands, [
+            (0, Target::Group(0), Some(true), Some(200), Some(0), Some(254), Some("select")),
+            alert(2000, 1, "none"),
+            alert(2000, 2, "none"),
+            reset(3000, 1),
+            reset(3000, 2),
+            (3000, Target::Group(0), Some(true), Some(150), Some(21845), Some(254), Some("lselect")),
+            alert(18000, 1, "lselect"),
+            alert(18000, 2, "lselect"),
+            reset(23000, 1),
+            reset(23000, 2),
+            (23000, Target::Group(0), Some(true), Some(100), Some(43690), Some(254), Some("select")),
+            reset(24000, 1),
+            reset(24000, 2),
+        ]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flushing_effect_drops_the_queued_ones_and_plays_next() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = AppState::new(mock.clone(), config_with(None, 254));
+        // The first plays while the others queue behind it
+        state.enqueue_effect(&effect(10)).unwrap();
+        state.enqueue_effect(&effect(20)).unwrap();
+        state.enqueue_effect(&effect(30)).unwrap();
+        state.enqueue_effect(&LightEffect { flush_queue: true, ..effect(40) }).unwrap();
+        tokio::select! {
+            _ = state.run_effect_queue() => unreachable!("the effect queue stays open"),
+            _ = sleep(Duration::from_secs(60)) => {}
+        }
+
+        let brightness: Vec<Option<u8>> = mock.sent().iter()
+            .filter(|sent| sent.command.alert.as_deref() == Some("select"))
+            .map(|sent| sent.command.bri)
+            .collect();
+        assert_eq!(brightness, [Some(10), Some(40)]);
+    }
+
+    /// The brightness of each effect played after queueing `effects` (brightness, priority) at once
+    /// with `max_active_effects: 2`.
+    async fn played_with_cap(policy: OverflowPolicy, effects: &[(u8, i32)]) -> Vec<Option<u8>> {
+        let mock = MockController::with_lights(&[1, 2]);
+        let mut config = config_with(None, 254);
+        config.max_active_effects = Some(2);
+        config.overflow_policy = policy;
+        let state = AppState::new(mock.clone(), config);
+        for &(brightness, priority) in effects {
+            state.enqueue_effect(&LightEffect { priority, ..effect(brightness) }).unwrap();
+        }
+        tokio::select! {
+            _ = state.run_effect_queue() => unreachable!("the effect queue stays open"),
+            _ = sleep(Duration::from_secs(60)) => {}
+        }
+        mock.sent().iter()
+            .filter(|sent| sent.command.alert.as_deref() == Some("select"))
+            .map(|sent| sent.command.bri)
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn effects_over_the_cap_are_dropped_per_policy() {
+        // The first plays while the rest arrive, leaving one slot for them
+        let effects = [(10, 9), (20, 1), (30, 3), (40, 2), (50, 0)];
+        assert_eq!(played_with_cap(OverflowPolicy::Oldest, &effects).await, [Some(10), Some(50)]);
+        assert_eq!(played_with_cap(OverflowPolicy::Newest, &effects).await, [Some(10), Some(20)]);
+        assert_eq!(played_with_cap(OverflowPolicy::LowestPriority, &effects).await, [Some(10), Some(30)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handlers_play_from_the_events_the_event_was_routed_on() {
+        let mock = MockController::with_lights(&[1]);
+        let mut config = config_with(None, 254);
+        config.events.donation.tiers = tiers(&[1.0]);
+        let mut routed = config.events.clone();
+        routed.donation.tiers = vec![TierEffect { amount: 1.0, effect: effect(200) }];
+        let state = AppState::new(mock.clone(), config);
+
+        // As if a show switch landed after the event was routed on `routed`
+        state.dispatch_event(&simulated_event(0, "donation:10").unwrap(), &routed).await.unwrap();
+        play_queued(&state).await;
+        assert_eq!(mock.sent().first().and_then(|sent| sent.command.bri), Some(200));
+    }
+
+    /// The MQTT and Discord message for `event`, handled as `handle_event` would.
+    async fn published(state: &AppState, event_type: &str, event_for: Option<&str>, message: serde_json::Value) -> serde_json::Value {
+        let event: StreamlabsEvent = serde_json::from_value(serde_json::json!({
+            "event_id": "", "for": event_for, "type": event_type, "message": [message],
+        })).unwrap();
+        let config = state.config();
+        let handled = HANDLED_EFFECT.scope(RefCell::new(HandledEffect::default()), async {
+            state.dispatch_event(&event, config.active_events()).await.unwrap();
+            HANDLED_EFFECT.with(|handled| handled.take())
+        }).await;
+        event_message(&event, &handled, true)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn published_amounts_are_the_ones_handlers_read() {
+        let mut config = config_with(None, 254);
+        config.events.donation.tiers = tiers(&[1.0]);
+        config.events.twitch_bits.tiers = tiers(&[1.0]);
+        config.events.youtube_superchat = EventTieredEffect { enabled: true, tiers: tiers(&[1.0]), ..Default::default() };
+        let state = AppState::new(MockController::with_lights(&[1]), config);
+
+        let bits = published(&state, "bits", Some("twitch_account"), serde_json::json!({ "name": "Cheerer", "amount": "1,000" })).await;
+        assert_eq!((bits["amount"].as_f64(), bits["tier"].as_f64()), (Some(1000.0), Some(1.0)));
+
+        let donation = serde_json::json!({ "name": "Tester", "amount": "5,00 €", "formatted_amount": "5,00 €" });
+        let donation = published(&state, "donation", None, donation).await;
+        assert_eq!((donation["amount"].as_f64(), donation["currency"].as_str()), (Some(5.0), Some("EUR")));
+
+        let superchat = serde_json::json!({ "name": "Viewer", "amount": "20000000", "currency": "usd" });
+        let superchat = published(&state, "superchat", Some("youtube_account"), superchat).await;
+        assert_eq!((superchat["amount"].as_f64(), superchat["currency"].as_str()), (Some(20.0), Some("USD")));
+    }
+
+    fn redemption_event(index: usize) -> StreamlabsEvent {
+        serde_json::from_value(serde_json::json!({
+            "event_id": format!("redemption-{}", index),
+            "for": "twitch_account",
+            "type": "redemption",
+            "message": [{ "name": "Viewer", "payload": { "name": "lights red", "id": "reward-1" } }],
+        })).unwrap()
+    }
+
+    #[test]
+    fn redemptions_read_a_bare_list_as_an_enabled_mapping() {
+        let reward = serde_json::json!({ "reward_name": "Lights Red", "effect": effect(254) });
+        let old: RedemptionEffects = serde_json::from_value(serde_json::json!([reward])).unwrap();
+        assert!(old.enabled);
+        assert_eq!(old.rewards.len(), 1);
+
+        let mapping = serde_json::json!({ "enabled": false, "priority": 3, "cooldown_ms": "30s", "rewards": [reward] });
+        let mapping: RedemptionEffects = serde_json::from_value(mapping).unwrap();
+        assert_eq!((mapping.enabled, mapping.priority, mapping.cooldown_ms), (false, 3, Some(30_000)));
+
+        let mut config = config_with(None, 254);
+        config.events.twitch_redemptions = mapping;
+        assert_eq!(config.events.enabled_states().get("twitch_redemptions"), Some(&false));
+        assert_eq!(config.events.priority("redemption"), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn redemptions_follow_their_cooldown_and_enabled_flag() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let mut config = config_with(None, 254);
+        config.events.twitch_redemptions = RedemptionEffects {
+            enabled: true,
+            cooldown_ms: Some(10_000),
+            rewards: vec![RedemptionEffect { reward_id: None, reward_name: Some("Lights Red".to_string()), effect: effect(200) }],
+            ..Default::default()
+        };
+        let state = AppState::new(mock.clone(), config);
+        state.handle_event(redemption_event(0)).await.unwrap();
+        state.handle_event(redemption_event(1)).await.unwrap();
+
+        let mut config = (*state.config()).clone();
+        *config.events.enabled_mut("twitch_redemptions").unwrap() = false;
+        *state.config.write() = Arc::new(config);
+        sleep(Duration::from_secs(11)).await;
+        state.handle_event(redemption_event(2)).await.unwrap();
+        play_queued(&state).await;
+
+        // Only the first played: the second was in its cooldown, the third disabled
+        let played = mock.sent().iter().filter(|sent| sent.command.bri == Some(200)).count();
+        assert_eq!(played, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn large_donation_plays_top_tier_on_every_light_then_resets() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = donation_state(&mock);
+        state.handle_donation(&donation("75"), state.config().active_events()).await.unwrap();
+        play_queued(&state).await;
+
+        let sent = mock.sent();
+        let targets: Vec<Target> = sent.iter().map(|sent| sent.target).collect();
+        assert_eq!(targets, [Target::Group(0), Target::Light(1), Target::Light(2)]);
+
+        let (hue, sat) = parse_color("#0000ff").unwrap();
+        let effect = &sent[0].command;
+        assert_eq!((effect.on, effect.bri, effect.hue, effect.sat), (Some(true), Some(254), Some(hue), Some(sat)));
+        assert_eq!(effect.alert.as_deref(), Some("select"));
+        for reset in &sent[1..] {
+            assert_default_state(&reset.command);
+            assert_eq!(reset.at - sent[0].at, Duration::from_millis(5000));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn small_donation_plays_low_tier_on_its_light_group() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = donation_state(&mock);
+        state.handle_donation(&donation("10"), state.config().active_events()).await.unwrap();
+        play_queued(&state).await;
+
+        let sent = mock.sent();
+        let targets: Vec<Target> = sent.iter().map(|sent| sent.target).collect();
+        assert_eq!(targets, [Target::Light(2), Target::Light(2)]);
+
+        let (hue, sat) = parse_color("#ff0000").unwrap();
+        let effect = &sent[0].command;
+        assert_eq!((effect.bri, effect.hue, effect.sat), (Some(100), Some(hue), Some(sat)));
+        assert_default_state(&sent[1].command);
+        assert_eq!(sent[1].at - sent[0].at, Duration::from_millis(2000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn metrics_count_events_duplicates_and_effects() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = donation_state(&mock);
+        // The same index gives the same event id, so the second is a duplicate
+        for _ in 0..2 {
+            state.handle_event(simulated_event(0, "donation:10").unwrap()).await.unwrap();
+        }
+        play_queued(&state).await;
+
+        let metrics = state.metrics.render();
+        for line in [
+            "lumialive_events_received_total{type=\"donation\"} 2",
+            "lumialive_events_ignored_total{reason=\"duplicate\"} 1",
+            "lumialive_effects_applied_total 1",
+            "lumialive_effects_failed_total 0",
+            "lumialive_effect_duration_seconds_count 1",
+        ] {
+            assert!(metrics.lines().any(|metric| metric == line), "missing {:?} in\n{}", line, metrics);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn events_within_their_cooldown_are_ignored() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = donation_state(&mock);
+        let mut config = (*state.config()).clone();
+        config.events.donation.cooldown_ms = Some(10_000);
+        *state.config.write() = Arc::new(config);
+        let mut queue = state.effect_queue.take_receiver().unwrap();
+
+        state.handle_event(simulated_event(0, "donation:10").unwrap()).await.unwrap();
+        assert!(queue.try_recv().is_ok());
+
+        tokio::time::advance(Duration::from_millis(9_000)).await;
+        state.handle_event(simulated_event(1, "donation:10").unwrap()).await.unwrap();
+        assert!(queue.try_recv().is_err(), "a donation 9 s into the cooldown played");
+        assert!(state.metrics.render().contains("lumialive_events_ignored_total{reason=\"cooldown\"} 1"));
+
+        tokio::time::advance(Duration::from_millis(1_000)).await;
+        state.handle_event(simulated_event(2, "donation:10").unwrap()).await.unwrap();
+        assert!(queue.try_recv().is_ok(), "a donation after the cooldown was ignored");
+    }
+
+    #[test]
+    fn dedup_buffer_forgets_the_oldest_id_past_its_capacity() {
+        let mut config = config_with(None, 254);
+        config.dedup_buffer_size = Some(2);
+        let state = AppState::new(MockController::default(), config);
+
+        assert!(!state.is_duplicate("a"));
+        assert!(!state.is_duplicate("b"));
+        assert!(state.is_duplicate("a"));
+        // "c" pushes out "a", the oldest
+        assert!(!state.is_duplicate("c"));
+        assert!(!state.is_duplicate("a"));
+        assert!(state.is_duplicate("c"));
+        assert_eq!(state.seen_event_ids.lock().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dashboard_lists_newest_events_first_and_the_last_effect() {
+        let mock = MockController::with_lights(&[1, 2]);
+        let state = donation_state(&mock);
+        for (index, spec) in ["donation:10", "donation:75"].into_iter().enumerate() {
+            state.handle_event(simulated_event(index, spec).unwrap()).await.unwrap();
+        }
+        play_queued(&state).await;
+
+        let amounts: Vec<Option<String>> = state.latest_event_records(5).into_iter().map(|event| event.amount).collect();
+        assert_eq!(amounts, [Some("75".to_string()), Some("10".to_string())]);
+        let last = state.last_effect.lock().clone().unwrap();
+        assert_eq!(last.effect.color, "#0000ff");
+        assert!(last.success);
+    }
+}
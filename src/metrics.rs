@@ -0,0 +1,139 @@
+//! Counters for a Prometheus scrape endpoint, served on their own port so they can stay on
+//! while the HTTP API is off.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{dev::Server, web, App, HttpResponse, HttpServer};
+use log::{error, info};
+use parking_lot::Mutex;
+
+use crate::{AppState, MetricsConfig};
+
+// Upper bounds in seconds of the effect duration histogram buckets
+const DURATION_BUCKETS: [f64; 9] = [0.5, 1.0, 2.5, 5.0, 10.0, 15.0, 30.0, 60.0, 120.0];
+
+#[derive(Default)]
+pub struct Metrics {
+    events_received: Mutex<BTreeMap<String, u64>>,
+    // Keyed by why the event was dropped before its handler ran
+    events_ignored: Mutex<BTreeMap<&'static str, u64>>,
+    effects_applied: AtomicU64,
+    effects_failed: AtomicU64,
+    bridge_errors: AtomicU64,
+    socket_reconnects: AtomicU64,
+    effect_durations: Mutex<Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Per bucket, not cumulative; the last one counts durations above every bound
+    counts: [u64; DURATION_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn event_received(&self, event_type: &str) {
+        *self.events_received.lock().entry(event_type.to_string()).or_default() += 1;
+    }
+
+    pub fn event_ignored(&self, reason: &'static str) {
+        *self.events_ignored.lock().entry(reason).or_default() += 1;
+    }
+
+    /// Counts an effect that ran for `elapsed`, including its reset.
+    pub fn effect_finished(&self, elapsed: Duration, success: bool) {
+        let counter = if success { &self.effects_applied } else { &self.effects_failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        let bucket = DURATION_BUCKETS.iter().position(|&bound| seconds <= bound).unwrap_or(DURATION_BUCKETS.len());
+        let mut histogram = self.effect_durations.lock();
+        histogram.counts[bucket] += 1;
+        histogram.sum += seconds;
+    }
+
+    pub fn bridge_error(&self) {
+        self.bridge_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn socket_reconnected(&self) {
+        self.socket_reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Everything in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, samples: Vec<(String, u64)>| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        counter(
+            "lumialive_events_received_total",
+            "Streamlabs events received, by event type.",
+            self.events_received.lock().iter().map(|(event_type, &count)| (format!("{{type=\"{}\"}}", escape(event_type)), count)).collect(),
+        );
+        counter(
+            "lumialive_events_ignored_total",
+            "Events dropped as duplicates or during their cooldown.",
+            self.events_ignored.lock().iter().map(|(reason, &count)| (format!("{{reason=\"{}\"}}", reason), count)).collect(),
+        );
+        counter("lumialive_effects_applied_total", "Light effects played to the end.", vec![(String::new(), self.effects_applied.load(Ordering::Relaxed))]);
+        counter("lumialive_effects_failed_total", "Light effects that failed.", vec![(String::new(), self.effects_failed.load(Ordering::Relaxed))]);
+        counter("lumialive_bridge_errors_total", "Light commands the lights rejected or never answered.", vec![(String::new(), self.bridge_errors.load(Ordering::Relaxed))]);
+        counter("lumialive_socket_reconnects_total", "Reconnects after the Streamlabs socket dropped.", vec![(String::new(), self.socket_reconnects.load(Ordering::Relaxed))]);
+
+        let histogram = self.effect_durations.lock();
+        let name = "lumialive_effect_duration_seconds";
+        let _ = writeln!(out, "# HELP {} How long effects took to play, including the reset.\n# TYPE {} histogram", name, name);
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += histogram.counts[DURATION_BUCKETS.len()];
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let _ = writeln!(out, "{}_sum {}", name, histogram.sum);
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub async fn serve(state: Arc<AppState>, config: MetricsConfig) {
+    info!("Serving metrics on http://{}:{}/metrics", config.bind, config.port);
+    match build_server(state, &config) {
+        Ok(server) => {
+            if let Err(e) = server.await {
+                error!("Metrics endpoint stopped: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to bind metrics endpoint on {}:{}: {}", config.bind, config.port, e),
+    }
+}
+
+fn build_server(state: Arc<AppState>, config: &MetricsConfig) -> std::io::Result<Server> {
+    let data = web::Data::from(state);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .route("/metrics", web::get().to(get_metrics))
+    })
+    .workers(1)
+    .bind((config.bind.as_str(), config.port))?
+    .run();
+    Ok(server)
+}
+
+async fn get_metrics(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.metrics.render())
+}
@@ -0,0 +1,68 @@
+//! Publishes every handled event to an MQTT broker, for Home Assistant and other automations.
+
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS};
+use serde_json::Value;
+
+use crate::{MqttConfig, MqttCredentials};
+
+// Publishes waiting for the connection before new ones are dropped
+const QUEUE_CAPACITY: usize = 64;
+const RECONNECT_DELAY_MS: u64 = 5_000;
+
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic: String,
+    retain: bool,
+}
+
+impl MqttPublisher {
+    /// The publisher and the event loop that does the network work; the loop has to be spawned
+    /// with `run` for anything to be sent.
+    pub fn new(config: &MqttConfig, credentials: Option<&MqttCredentials>) -> (Self, EventLoop) {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(credentials) = credentials {
+            options.set_credentials(&credentials.username, &credentials.password);
+        }
+        let (client, eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let publisher = Self {
+            client,
+            topic: config.topic.clone(),
+            retain: config.retain,
+        };
+        (publisher, eventloop)
+    }
+
+    /// Queues `message` without waiting; while the broker is unreachable, messages beyond the
+    /// queue's capacity are dropped.
+    pub fn publish(&self, message: &Value) {
+        if let Err(e) = self.client.try_publish(&self.topic, QoS::AtLeastOnce, self.retain, message.to_string()) {
+            warn!("Dropping MQTT message for {}: {}", self.topic, e);
+        }
+    }
+
+    /// Disconnects once the messages queued so far have been sent.
+    pub async fn close(&self) {
+        if let Err(e) = self.client.disconnect().await {
+            debug!("Error disconnecting from the MQTT broker: {}", e);
+        }
+    }
+}
+
+/// Drives the connection, reconnecting after a delay whenever it fails, until `close`.
+pub async fn run(mut eventloop: EventLoop) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => info!("Connected to the MQTT broker"),
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => return,
+            Ok(_) => {}
+            Err(e) => {
+                warn!("MQTT connection failed ({}), retrying in {} ms", e, RECONNECT_DELAY_MS);
+                tokio::time::sleep(Duration::from_millis(RECONNECT_DELAY_MS)).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! WLED LED strips over their HTTP JSON API, as an alternative to the Hue bridge.
+//!
+//! Each entry in `wled.devices` is one light, numbered from 1 in the order listed. Hue's
+//! hue/saturation, xy and color temperature are all turned into the RGB color WLED's first
+//! segment color takes, and brightness is scaled from 0-254 to 0-255. Strobes and rainbows can
+//! play as WLED's own built-in effects instead of a stream of commands.
+//!
+//! Requests are plain HTTP/1.1 over a fresh connection each, which is all WLED serves; the
+//! controller is called from inside the async runtime, where a blocking HTTP client can't run.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use hueclient::{CommandLight, HueError, IdentifiedGroup, IdentifiedLight};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+
+use crate::controller::{Builtin, ControllerError, LightController};
+use crate::{rgb_to_hue, srgb_to_linear, AppError, WledConfig};
+
+// WLED's "Solid" effect, which just shows the segment color
+const SOLID_EFFECT: u64 = 0;
+
+struct Device {
+    // host[:port], port 80 when left out
+    host: String,
+    addr: SocketAddr,
+    name: String,
+    // Built-in effect names; the index is the effect id
+    effects: Vec<String>,
+}
+
+pub struct WledController {
+    timeout: Duration,
+    devices: Vec<Device>,
+    strobe_effect: Option<String>,
+    rainbow_effect: Option<String>,
+}
+
+impl WledController {
+    /// Checks every configured device answers and reads its name and effect list.
+    pub fn connect(config: &WledConfig) -> Result<Self, AppError> {
+        if config.devices.is_empty() {
+            return Err(AppError::Config("backend is \"wled\" but wled.devices is empty".to_string()));
+        }
+        let mut controller = Self {
+            timeout: Duration::from_millis(config.timeout_ms),
+            devices: Vec::new(),
+            strobe_effect: config.strobe_effect.clone(),
+            rainbow_effect: config.rainbow_effect.clone(),
+        };
+
+        for (index, address) in config.devices.iter().enumerate() {
+            let host = address.trim_start_matches("http://").trim_end_matches('/').to_string();
+            let with_port = if host.contains(':') { host.clone() } else { format!("{}:80", host) };
+            let addr = with_port.to_socket_addrs()?.next()
+                .ok_or_else(|| AppError::Config(format!("Invalid WLED device {:?}", address)))?;
+            let mut device = Device { host, addr, name: address.clone(), effects: Vec::new() };
+
+            let info = controller.request(&device, "GET", "/json/info", None)
+                .map_err(|e| AppError::Bridge(format!("WLED device {} is not responding: {}", address, e)))?;
+            if let Some(name) = info["name"].as_str() {
+                device.name = name.to_string();
+            }
+            match controller.request(&device, "GET", "/json/effects", None) {
+                Ok(effects) => device.effects = serde_json::from_value(effects).unwrap_or_default(),
+                Err(e) => warn!("Couldn't read WLED effects from {}: {}", address, e),
+            }
+            info!("WLED light {}: {:?} at {}", index + 1, device.name, device.host);
+            controller.devices.push(device);
+        }
+        Ok(controller)
+    }
+
+    /// Sends one request and parses the JSON it answers with.
+    fn request(&self, device: &Device, method: &str, path: &str, body: Option<&Value>) -> io::Result<Value> {
+        let body = body.map(Value::to_string).unwrap_or_default();
+        let mut stream = TcpStream::connect_timeout(&device.addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method, path, device.host, body.len(), body
+        )?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let body = response_body(&response)?;
+        serde_json::from_slice(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn post_state(&self, device: &Device, state: &Value) -> Result<(), ControllerError> {
+        debug!("WLED {} <- {}", device.name, state);
+        self.request(device, "POST", "/json/state", Some(state)).map(drop).map_err(protocol_error)
+    }
+
+    fn device(&self, id: usize) -> Result<&Device, ControllerError> {
+        id.checked_sub(1).and_then(|index| self.devices.get(index))
+            .ok_or_else(|| HueError::ProtocolError { msg: format!("No WLED light {}", id) }.into())
+    }
+}
+
+impl LightController for WledController {
+    fn get_lights(&self) -> Result<Vec<IdentifiedLight>, ControllerError> {
+        self.devices.iter().enumerate().map(|(index, device)| {
+            let state = self.request(device, "GET", "/json/state", None).map_err(protocol_error)?;
+            let rgb = &state["seg"][0]["col"][0];
+            let channel = |i: usize| rgb[i].as_u64().unwrap_or(0).min(255) as f32 / 255.0;
+            let (hue, sat) = rgb_to_hue(channel(0), channel(1), channel(2));
+            Ok(IdentifiedLight {
+                id: index + 1,
+                light: hueclient::Light {
+                    name: device.name.clone(),
+                    modelid: String::new(),
+                    swversion: String::new(),
+                    uniqueid: device.host.clone(),
+                    state: hueclient::LightState {
+                        on: state["on"].as_bool().unwrap_or(false),
+                        bri: state["bri"].as_u64().map(|bri| (bri.min(255) * 254 / 255) as u8),
+                        hue: Some(hue),
+                        sat: Some(sat),
+                        ct: None,
+                        xy: None,
+                    },
+                },
+            })
+        }).collect()
+    }
+
+    /// WLED has no bridge groups; group 0 still means every light.
+    fn get_groups(&self) -> Result<Vec<IdentifiedGroup>, ControllerError> {
+        Ok(Vec::new())
+    }
+
+    fn set_light(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        let device = self.device(id)?;
+        let Some(state) = wled_state(command) else {
+            if command.alert.as_deref().is_some_and(|alert| alert != "none") {
+                debug!("WLED light {:?} has no alerts, ignoring {:?}", device.name, command.alert);
+            }
+            return Ok(());
+        };
+        self.post_state(device, &state)
+    }
+
+    fn set_group(&self, id: usize, command: &CommandLight) -> Result<(), ControllerError> {
+        if id != 0 {
+            return Err(HueError::ProtocolError { msg: format!("WLED has no group {}", id) }.into());
+        }
+        for light in 1..=self.devices.len() {
+            self.set_light(light, command)?;
+        }
+        Ok(())
+    }
+
+    /// Switches the first segment to the configured built-in effect, keeping its colors.
+    fn play_builtin(&self, id: usize, builtin: Builtin) -> Result<bool, ControllerError> {
+        let device = self.device(id)?;
+        let name = match builtin {
+            Builtin::Strobe => &self.strobe_effect,
+            Builtin::Rainbow => &self.rainbow_effect,
+        };
+        let Some(name) = name else {
+            return Ok(false);
+        };
+        let Some(effect) = device.effects.iter().position(|effect| effect.eq_ignore_ascii_case(name)) else {
+            warn!("WLED light {:?} has no effect named {:?}", device.name, name);
+            return Ok(false);
+        };
+        self.post_state(device, &json!({"on": true, "seg": [{"fx": effect}]}))?;
+        Ok(true)
+    }
+}
+
+/// The `/json/state` body for a command, or `None` when nothing in it applies to WLED. Any
+/// color also switches back to the solid effect, ending a built-in strobe or rainbow.
+fn wled_state(command: &CommandLight) -> Option<Value> {
+    let mut state = serde_json::Map::new();
+    if let Some(on) = command.on {
+        state.insert("on".to_string(), json!(on));
+    }
+    if let Some(bri) = command.bri {
+        state.insert("bri".to_string(), json!(bri as u32 * 255 / 254));
+    }
+    if let Some(time) = command.transitiontime {
+        // Both count tenths of a second
+        state.insert("transition".to_string(), json!(time));
+    }
+    if let Some((r, g, b)) = command_rgb(command) {
+        let rgb: Vec<u8> = [r, g, b].into_iter().map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8).collect();
+        state.insert("seg".to_string(), json!([{"fx": SOLID_EFFECT, "col": [rgb]}]));
+    }
+    (!state.is_empty()).then_some(Value::Object(state))
+}
+
+/// A command's color as linear RGB at full brightness, from whichever of xy, color temperature
+/// or hue/saturation it sets. A saturation alone keeps no hue, so it is taken as red.
+fn command_rgb(command: &CommandLight) -> Option<(f32, f32, f32)> {
+    if let Some((x, y)) = command.xy {
+        return Some(xy_to_rgb(x, y));
+    }
+    if let Some(mirek) = command.ct {
+        return Some(kelvin_to_rgb(1_000_000.0 / mirek.max(1) as f32));
+    }
+    if command.hue.is_none() && command.sat.is_none() {
+        return None;
+    }
+    let hue = command.hue.unwrap_or(0) as f32 / 65535.0 * 360.0;
+    let sat = command.sat.unwrap_or(254).min(254) as f32 / 254.0;
+    Some(hsv_to_rgb(hue, sat))
+}
+
+fn hsv_to_rgb(hue: f32, sat: f32) -> (f32, f32, f32) {
+    let sector = (hue % 360.0) / 60.0;
+    let x = sat * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (sat, x, 0.0),
+        1 => (x, sat, 0.0),
+        2 => (0.0, sat, x),
+        3 => (0.0, x, sat),
+        4 => (x, 0.0, sat),
+        _ => (sat, 0.0, x),
+    };
+    let m = 1.0 - sat;
+    (r + m, g + m, b + m)
+}
+
+/// The inverse of the Philips wide gamut matrix `rgb_to_xy` uses, scaled so the brightest
+/// channel is 1.
+fn xy_to_rgb(x: f32, y: f32) -> (f32, f32, f32) {
+    let (big_x, big_z) = (x / y.max(0.0001), (1.0 - x - y) / y.max(0.0001));
+    let r = big_x * 1.656_492 - 0.354_851 - big_z * 0.255_038;
+    let g = -big_x * 0.707_196 + 1.655_397 + big_z * 0.036_152;
+    let b = big_x * 0.051_713 - 0.121_364 + big_z * 1.011_53;
+    let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+    let max = r.max(g).max(b).max(f32::EPSILON);
+    (r / max, g / max, b / max)
+}
+
+/// Approximates a black body at `kelvin` as linear RGB, following the usual curve fit.
+fn kelvin_to_rgb(kelvin: f32) -> (f32, f32, f32) {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.699 * (t - 60.0).powf(-0.133_205) };
+    let g = if t <= 66.0 { 99.470_8 * t.ln() - 161.119_57 } else { 288.122 * (t - 60.0).powf(-0.075_515) };
+    let b = if t >= 66.0 { 255.0 } else if t <= 19.0 { 0.0 } else { 138.517_73 * (t - 10.0).ln() - 305.044_8 };
+    let channel = |c: f32| srgb_to_linear(c.clamp(0.0, 255.0) / 255.0);
+    (channel(r), channel(g), channel(b))
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c > 0.003_130_8 {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    } else {
+        c * 12.92
+    }
+}
+
+/// The body of a `200 OK` response, with chunked transfer encoding undone.
+fn response_body(response: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let split = response.windows(4).position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("truncated HTTP response".to_string()))?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(invalid(format!("unexpected response {:?}", status)));
+    }
+    let mut body = &response[split + 4..];
+    let chunked = head.lines().any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+    if !chunked {
+        return Ok(body.to_vec());
+    }
+
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")
+            .ok_or_else(|| invalid("truncated chunk".to_string()))?;
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)
+            .map_err(|_| invalid(format!("bad chunk size {:?}", size)))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk = body.get(..size).ok_or_else(|| invalid("truncated chunk".to_string()))?;
+        decoded.extend_from_slice(chunk);
+        body = body.get(size + 2..).unwrap_or_default();
+    }
+}
+
+fn protocol_error(e: io::Error) -> ControllerError {
+    HueError::ProtocolError { msg: format!("WLED: {}", e) }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rgb(actual: (f32, f32, f32), expected: (f32, f32, f32)) {
+        let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+        assert!(close(actual.0, expected.0) && close(actual.1, expected.1) && close(actual.2, expected.2), "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn plain_and_chunked_bodies_are_read() {
+        let plain = b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"on\":true}";
+        assert_eq!(response_body(plain).unwrap(), b"{\"on\":true}");
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{\"on\r\n7;ext=1\r\n\":true}\r\n0\r\n\r\n";
+        assert_eq!(response_body(chunked).unwrap(), b"{\"on\":true}");
+    }
+
+    #[test]
+    fn other_statuses_and_truncated_responses_fail() {
+        let not_found = response_body(b"HTTP/1.1 404 Not Found\r\n\r\nnope").unwrap_err();
+        assert!(not_found.to_string().contains("404"), "{}", not_found);
+        assert!(response_body(b"HTTP/1.1 200 OK\r\nContent-Le").is_err());
+        assert!(response_body(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n10\r\nshort").is_err());
+        assert!(response_body(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").is_err());
+    }
+
+    #[test]
+    fn command_becomes_a_wled_state() {
+        assert_eq!(wled_state(&CommandLight::default()), None);
+
+        let command = CommandLight {
+            on: Some(true),
+            bri: Some(254),
+            hue: Some(0),
+            sat: Some(254),
+            transitiontime: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(wled_state(&command), Some(json!({
+            "on": true,
+            "bri": 255,
+            "transition": 4,
+            "seg": [{"fx": SOLID_EFFECT, "col": [[255, 0, 0]]}],
+        })));
+
+        let off = CommandLight { on: Some(false), ..Default::default() };
+        assert_eq!(wled_state(&off), Some(json!({"on": false})));
+    }
+
+    #[test]
+    fn hsv_covers_each_sector() {
+        assert_rgb(hsv_to_rgb(0.0, 1.0), (1.0, 0.0, 0.0));
+        assert_rgb(hsv_to_rgb(60.0, 1.0), (1.0, 1.0, 0.0));
+        assert_rgb(hsv_to_rgb(120.0, 1.0), (0.0, 1.0, 0.0));
+        assert_rgb(hsv_to_rgb(240.0, 1.0), (0.0, 0.0, 1.0));
+        assert_rgb(hsv_to_rgb(300.0, 0.5), (1.0, 0.5, 1.0));
+        assert_rgb(hsv_to_rgb(360.0, 1.0), (1.0, 0.0, 0.0));
+        // No saturation is white whatever the hue
+        assert_rgb(hsv_to_rgb(200.0, 0.0), (1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn kelvin_runs_from_warm_to_white() {
+        assert_rgb(kelvin_to_rgb(6600.0), (1.0, 1.0, 1.0));
+        let (r, g, b) = kelvin_to_rgb(2000.0);
+        assert_eq!(r, 1.0);
+        assert!(g < r && b < g, "{:?}", (r, g, b));
+        // Below the curve's range is as warm as it gets
+        assert_eq!(kelvin_to_rgb(500.0), kelvin_to_rgb(1000.0));
+    }
+
+    #[test]
+    fn xy_inverts_rgb_to_xy() {
+        let [x, y] = crate::rgb_to_xy(1.0, 0.5, 0.0);
+        assert_rgb(xy_to_rgb(x, y), (1.0, srgb_to_linear(0.5), 0.0));
+        let [x, y] = crate::rgb_to_xy(0.0, 0.0, 1.0);
+        assert_rgb(xy_to_rgb(x, y), (0.0, 0.0, 1.0));
+    }
+}